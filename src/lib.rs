@@ -0,0 +1,3185 @@
+//! Read technical metadata and tags from audio files.
+//!
+//! [`analyze`] covers the common case; [`analyze_with`] and [`Options`] turn on
+//! the slower decode-based measurements.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use convert_case::{Case, Casing};
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, Read, Seek, SeekFrom, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+use symphonia::core::{
+    audio::{AudioBufferRef, Channels, SampleBuffer},
+    codecs::{
+        self, CODEC_TYPE_NULL, CodecParameters, CodecType, DecoderOptions, VerificationCheck,
+    },
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::{MediaSource, MediaSourceStream, ReadBytes},
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey, Tag, Value, Visual},
+    probe::{Hint, ProbeResult},
+};
+use unicode_normalization::UnicodeNormalization;
+
+/// What to measure beyond the header metadata, and how hard to try.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Compute EBU R128 integrated loudness and loudness range
+    pub loudness: bool,
+    /// Report overall and per-channel sample peaks
+    pub peaks: bool,
+    /// Estimate the frequency cutoff
+    pub spectral: bool,
+    /// Look for the steep low-pass a sample rate conversion leaves, in
+    /// `AudioInfo::likely_resampled`
+    pub resample_check: bool,
+    /// Check FLAC audio against the MD5 stored in its header
+    pub verify_md5: bool,
+    /// Decode the whole file to measure its duration instead of trusting the header
+    pub verify_duration: bool,
+    /// Decode the whole file to count corrupt packets
+    pub check: bool,
+    /// Decode audio to estimate the tempo when the file has no BPM tag
+    pub detect_bpm: bool,
+    /// Decode a stretch of audio to tell speech from music, in
+    /// `AudioInfo::content_type`
+    pub classify: bool,
+    /// Decode the first packet to report the decoder's sample format and
+    /// channel order
+    pub decode_info: bool,
+    /// Open a second format reader and try seeking it, for `seekable` and
+    /// `seek_accuracy`
+    pub seek_test: bool,
+    /// Sort tags by key instead of keeping file order
+    pub sort_tags: bool,
+    /// How to resolve tags whose keys normalize to the same name
+    pub duplicate_tags: DuplicateTags,
+    /// Bring tag keys and values into this Unicode normalization form, so text
+    /// written by different systems compares equal
+    pub unicode_form: Option<UnicodeForm>,
+    /// Retry this many times on transient I/O errors
+    pub retries: u32,
+    /// Keep the image data of embedded pictures, not just their details
+    pub art_data: bool,
+    /// Probe as this format (an extension like `flac` or a MIME type like
+    /// `audio/flac`) instead of going by the file's extension
+    pub input_format: Option<String>,
+    /// Record how derived fields (duration, bitrate, ...) were computed, in
+    /// `AudioInfo::explain`
+    pub explain: bool,
+    /// Program that decrypts DRM-protected audio: it reads the file on stdin and
+    /// writes the decrypted file to stdout
+    pub decrypt_helper: Option<PathBuf>,
+    /// Key handed to `decrypt_helper`, in hex in `AUDIOMETA_KEY`
+    pub key: Option<Key>,
+    /// Report this audio track, counting from 1, instead of the first
+    pub track: Option<usize>,
+    /// Keep binary tags and pictures stored as text (such as Vorbis
+    /// `COVERART`) in `AudioInfo::tags` instead of skipping them
+    pub binary_tags: bool,
+}
+
+/// A key for encrypted audio, passed on to the decryption helper.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key(pub Vec<u8>);
+
+// Keep keys out of logs and out of the `--cache` file, which stamps a digest instead
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// What to do with several tags that normalize to the same key, such as
+/// `Artist` and `ARTIST`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateTags {
+    /// Keep every entry
+    #[default]
+    Keep,
+    /// Keep the first value
+    First,
+    /// Keep the last value
+    Last,
+    /// Join the distinct values with `; `
+    Join,
+}
+
+/// A Unicode normalization form for tag text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Composed, e.g. `é` as one code point
+    Nfc,
+    /// Decomposed, e.g. `é` as `e` plus a combining accent
+    Nfd,
+}
+
+impl UnicodeForm {
+    fn apply(self, text: &str) -> String {
+        match self {
+            UnicodeForm::Nfc => text.nfc().collect(),
+            UnicodeForm::Nfd => text.nfd().collect(),
+        }
+    }
+}
+
+/// Why a file couldn't be found or analyzed.
+#[derive(Debug, thiserror::Error)]
+pub enum AudioMetaError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Http(#[from] ureq::Error),
+    /// No reader recognized the file, or it is malformed
+    #[error(transparent)]
+    Probe(SymphoniaError),
+    /// Decoding failed part-way through a decode pass
+    #[error(transparent)]
+    Decode(#[from] SymphoniaError),
+    #[error(transparent)]
+    Loudness(#[from] ebur128::Error),
+    /// A format symphonia has no reader for, named after the file extension
+    #[error("unsupported codec: {0} is not supported")]
+    Unsupported(&'static str),
+    #[error("No supported audio track")]
+    NoTrack,
+    /// The requested audio track is past the last one, which is the count
+    #[error("no audio track {0}: the file has {1}")]
+    NoSuchTrack(usize, usize),
+    /// The audio is encrypted, named after the protection scheme
+    #[error("DRM-protected ({0}), cannot read audio")]
+    Protected(&'static str),
+    /// The decryption helper couldn't be run or failed, with its message
+    /// (such as a wrong key)
+    #[error("decryption helper failed: {0}")]
+    DecryptHelper(String),
+    /// The track lacks a parameter a decode pass needs
+    #[error("Unknown {0}")]
+    MissingParameter(&'static str),
+    #[error("empty file")]
+    Empty,
+    #[error("{} is a directory (use --recursive to process directories)", .0.display())]
+    IsDirectory(PathBuf),
+    #[error("File not found: {}", .0.display())]
+    NotFound(PathBuf),
+    /// A tar archive couldn't be listed
+    #[error("{}: {}", .0.display(), .1)]
+    Archive(PathBuf, io::Error),
+    /// A directory (or an entry in one) couldn't be read during a walk
+    #[error("cannot read {}: {}", .0.display(), .1)]
+    Walk(PathBuf, io::Error),
+}
+
+impl AudioMetaError {
+    /// Whether the file is in a codec or format that isn't supported, rather
+    /// than broken.
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            AudioMetaError::Unsupported(_)
+                | AudioMetaError::Probe(SymphoniaError::Unsupported(_))
+                | AudioMetaError::Decode(SymphoniaError::Unsupported(_))
+        )
+    }
+}
+
+/// Technical metadata and tags read from one audio file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioInfo {
+    pub file_path: String,
+    /// Short hash of the canonical path (or URL), stable across runs for joins
+    pub id: String,
+    pub sample_rate: Option<u32>,
+    /// Rate of the encoded source when it differs from the output rate (Opus)
+    pub original_sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    /// Bit depth of the decoded samples, for PCM and lossless codecs
+    pub bits_per_sample: Option<u32>,
+    pub duration_seconds: Option<u64>,
+    /// Whole-file size over duration, so other streams (video, etc.) inflate it
+    pub avg_bitrate_kbps: Option<u32>,
+    pub tags: Vec<(String, String)>,
+    pub file_size_bytes: u64,
+    /// When the file was last modified, as RFC 3339 in UTC; local files only
+    pub modified_time: Option<String>,
+    /// When the file was created, where the platform and filesystem record it
+    pub created_time: Option<String>,
+    pub codec: Option<String>,
+    /// Codec profile where the file records one, e.g. `LC` or `HE-AAC` for AAC
+    pub codec_profile: Option<String>,
+    /// Container format, sniffed from the file's leading bytes
+    pub container: Option<String>,
+    /// Whether the audio is DRM-protected (FairPlay, Audible AAX, CENC); only
+    /// checked for MP4 files
+    pub drm: Option<bool>,
+    pub integrated_lufs: Option<f64>,
+    pub loudness_range_lu: Option<f64>,
+    pub sample_peak: Option<f64>,
+    pub channel_peaks: Vec<f64>,
+    pub audio_data_offset: Option<u64>,
+    /// Bytes outside the audio data: everything before `audio_data_offset`,
+    /// plus tag blocks after the audio (ID3v1, APE, trailing RIFF chunks)
+    pub metadata_size_bytes: Option<u64>,
+    pub stream_count: usize,
+    /// Number of audio tracks, those with a codec
+    pub track_count: usize,
+    /// Which audio track is reported, counting from 1
+    pub track_index: usize,
+    pub vendor: Option<String>,
+    pub frequency_cutoff_hz: Option<f64>,
+    /// Whether the spectrum ends in a steep low-pass just under another
+    /// standard rate's Nyquist frequency, as sample rate conversion leaves
+    pub likely_resampled: Option<bool>,
+    /// MD5 of the unencoded audio, as stored in a FLAC STREAMINFO block
+    pub flac_audio_md5: Option<String>,
+    pub flac_md5_ok: Option<bool>,
+    /// The header promises noticeably more audio data than the file holds
+    pub likely_truncated: Option<bool>,
+    /// LAME preset from the LAME tag, e.g. `V0`, `standard` or `ABR 192`
+    pub lame_preset: Option<String>,
+    /// VBR quality (0 = V0 ... 9 = V9) for VBR-encoded LAME files
+    pub lame_vbr_quality: Option<u8>,
+    /// Whether the file carries unsynchronized or synchronized lyrics
+    pub has_lyrics: bool,
+    /// Unsynchronized lyrics text (ID3v2 USLT, Vorbis LYRICS, ...)
+    pub lyrics: Option<String>,
+    /// Synchronized lyrics (ID3v2 SYLT) in LRC format
+    pub synced_lyrics: Option<String>,
+    /// International Standard Recording Code, e.g. `USRC17607839`
+    pub isrc: Option<String>,
+    /// Release barcode (UPC/EAN)
+    pub barcode: Option<String>,
+    pub musicbrainz_trackid: Option<String>,
+    pub musicbrainz_albumid: Option<String>,
+    /// Tempo in beats per minute, from the BPM tag or (with `detect_bpm`)
+    /// estimated from the audio
+    pub bpm: Option<f64>,
+    /// Whether `bpm` was estimated rather than read from a tag
+    pub bpm_estimated: Option<bool>,
+    /// `speech`, `music` or `unknown`, from a decoded stretch of audio
+    /// (with `classify`)
+    pub content_type: Option<String>,
+    /// Whole-file size over the size of the audio as raw PCM, for lossless codecs
+    pub compression_ratio: Option<f64>,
+    /// Duration the header declares, in seconds (with `verify_duration`)
+    pub declared_duration_seconds: Option<f64>,
+    /// Duration of the audio actually decoded, in seconds (with `verify_duration`)
+    pub decoded_duration_seconds: Option<f64>,
+    /// Corrupt packets skipped during a decode pass
+    pub decode_errors: Option<usize>,
+    /// Sample format the decoder produces, e.g. `s16` or `f32` (with
+    /// `decode_info`)
+    pub sample_format: Option<String>,
+    /// Order of the channels within a frame, e.g. `front_left`, `front_right`
+    /// (with `decode_info`)
+    pub channel_order: Vec<String>,
+    /// Most frames a single decoded packet holds (with `decode_info`)
+    pub max_frames_per_packet: Option<u64>,
+    /// Whether symphonia can seek within the track (with `seek_test`, for
+    /// tracks of known length)
+    pub seekable: Option<bool>,
+    /// How precisely symphonia seeks by time: `accurate` (to the requested
+    /// sample), `coarse` (only to somewhere near it) or `none` (with
+    /// `seek_test`)
+    pub seek_accuracy: Option<String>,
+    /// Whether the file has an index for fast seeking (FLAC SEEKTABLE, MP3
+    /// TOC, MP4 sample tables, Matroska Cues)
+    pub has_seek_index: Option<bool>,
+    /// Tag blocks and where each sits relative to the audio
+    pub metadata_blocks: Vec<MetadataBlock>,
+    /// Top-level chunks of a RIFF (WAV) file, in file order
+    pub chunks: Vec<RiffChunk>,
+    /// Number of tags in the file, before any deduplication
+    pub tag_count: usize,
+    /// Total size of the tag blocks, or of the tag values and pictures when
+    /// a block's size isn't known
+    pub tag_bytes: u64,
+    /// Whether any tag block comes after the audio, which a player streaming
+    /// the file only sees once it has read everything else
+    pub metadata_at_end: Option<bool>,
+    /// Embedded pictures (cover art and the like)
+    pub art: Vec<Artwork>,
+    /// Soft problems that didn't stop the file being read
+    pub warnings: Vec<String>,
+    /// How each derived field was computed, as `(field, explanation)` pairs
+    /// (with `explain`)
+    pub explain: Vec<(String, String)>,
+}
+
+impl AudioInfo {
+    /// Note how `field` was computed, replacing any earlier note for it.
+    fn explain(&mut self, enabled: bool, field: &str, how: impl FnOnce() -> String) {
+        if enabled {
+            self.explain.retain(|(f, _)| f != field);
+            self.explain.push((field.to_string(), how()));
+        }
+    }
+}
+
+/// A picture embedded in a file's tags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artwork {
+    /// What the picture shows, e.g. `front_cover`, when the tag says
+    pub kind: Option<String>,
+    /// MIME type, e.g. `image/jpeg`
+    pub media_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub size_bytes: usize,
+    /// The image itself; empty unless `Options::art_data` is set
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl Artwork {
+    fn new(visual: &Visual, keep_data: bool) -> Self {
+        Artwork {
+            kind: visual
+                .usage
+                .map(|usage| format!("{usage:?}").to_case(Case::Snake)),
+            media_type: visual.media_type.clone(),
+            width: visual.dimensions.map(|size| size.width),
+            height: visual.dimensions.map(|size| size.height),
+            size_bytes: visual.data.len(),
+            data: if keep_data {
+                visual.data.to_vec()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// A block of tags found in a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataBlock {
+    /// `id3v2`, `id3v1`, `ape`, `vorbis_comment`, `flac_picture`, `mp4` (the
+    /// `moov` box) or `riff_info`/`riff_id3`
+    pub format: String,
+    /// Whether the block comes after the audio data rather than before it
+    pub at_end: bool,
+    /// Size of the block in bytes, where the file records it (for MP4, of the
+    /// tag boxes within `moov`)
+    pub size_bytes: Option<u64>,
+}
+
+/// A top-level chunk of a RIFF file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiffChunk {
+    /// Four-character id, e.g. `fmt `, `data`, `LIST` or `bext`
+    pub id: String,
+    /// Size of the chunk's data as its header records it
+    pub size_bytes: u64,
+}
+
+/// Something to analyze: a local file, a remote `http(s)://` URL or a file
+/// inside a tar archive.
+pub enum Input {
+    File(PathBuf),
+    Url(String),
+    Archived(ArchiveEntry),
+}
+
+impl std::fmt::Display for Input {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Input::File(path) => write!(f, "{}", path.display()),
+            Input::Url(url) => f.write_str(url),
+            Input::Archived(entry) => write!(f, "{}!{}", entry.archive.display(), entry.name),
+        }
+    }
+}
+
+/// A regular file inside a tar archive, found by `archive_entries`.
+pub struct ArchiveEntry {
+    /// The archive, as it was named
+    pub archive: PathBuf,
+    /// The entry's path inside the archive
+    pub name: String,
+    /// Where the entry's data starts in `data`, and how long it is
+    offset: u64,
+    size: u64,
+    /// The tar data to read from: the archive itself, or a decompressed copy
+    data: Arc<TarData>,
+}
+
+enum TarData {
+    Plain(PathBuf),
+    /// An anonymous temporary file, which the OS removes however we exit
+    Decompressed(Mutex<File>),
+}
+
+impl TarData {
+    /// Read `size` bytes at `offset`.
+    fn read(&self, offset: u64, size: u64) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        match self {
+            TarData::Plain(path) => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                file.take(size).read_to_end(&mut body)?;
+            }
+            TarData::Decompressed(file) => {
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                file.seek(SeekFrom::Start(offset))?;
+                (&mut *file).take(size).read_to_end(&mut body)?;
+            }
+        }
+        Ok(body)
+    }
+}
+
+/// Whether `path` names a tar archive: `.tar`, or gzipped as `.tar.gz`/`.tgz`.
+pub fn is_archive(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// The audio files inside a tar archive, in archive order. A gzipped archive
+/// is decompressed once into a temporary file, which lives as long as its
+/// entries, so each entry can then be read directly.
+pub fn archive_entries(archive: &Path, exts: &[String]) -> Result<Vec<Input>, AudioMetaError> {
+    list_archive(archive, exts).map_err(|e| AudioMetaError::Archive(archive.to_path_buf(), e))
+}
+
+fn list_archive(archive: &Path, exts: &[String]) -> io::Result<Vec<Input>> {
+    let (data, file) = if archive.to_string_lossy().to_lowercase().ends_with(".tar") {
+        (TarData::Plain(archive.to_path_buf()), File::open(archive)?)
+    } else {
+        let mut decompressed = tempfile::tempfile()?;
+        io::copy(
+            &mut flate2::read::GzDecoder::new(File::open(archive)?),
+            &mut decompressed,
+        )?;
+        decompressed.seek(SeekFrom::Start(0))?;
+        (
+            TarData::Decompressed(Mutex::new(decompressed.try_clone()?)),
+            decompressed,
+        )
+    };
+
+    let mut tar = tar::Archive::new(file);
+    let mut found = Vec::new();
+    for entry in tar.entries_with_seek()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?;
+        if is_audio_file(&name, exts) {
+            found.push((
+                name.to_string_lossy().into_owned(),
+                entry.raw_file_position(),
+                entry.size(),
+            ));
+        }
+    }
+
+    let data = Arc::new(data);
+    Ok(found
+        .into_iter()
+        .map(|(name, offset, size)| {
+            Input::Archived(ArchiveEntry {
+                archive: archive.to_path_buf(),
+                name,
+                offset,
+                size,
+                data: Arc::clone(&data),
+            })
+        })
+        .collect())
+}
+
+pub fn is_url(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
+
+/// The extension of a URL's last path segment, ignoring any query or fragment.
+pub fn url_extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let (_, name) = path.rsplit_once('/')?;
+    name.rsplit_once('.').map(|(_, ext)| ext)
+}
+
+/// A tag's key in snake_case, preferring symphonia's standard name for it.
+pub fn normalize_key(tag: &Tag) -> String {
+    tag.std_key
+        .map(|k| format!("{k:?}"))
+        .unwrap_or_else(|| tag.key.clone())
+        .to_case(Case::Snake)
+}
+
+/// How to expand directories into the audio files below them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Walk {
+    /// Walk directories at all; without this a directory is an error
+    pub recursive: bool,
+    /// Visit each directory's entries by file name rather than in filesystem
+    /// order
+    pub sorted: bool,
+    pub order: WalkOrder,
+}
+
+/// The order a directory walk visits subdirectories in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Each subdirectory in full before the next entry
+    #[default]
+    DepthFirst,
+    /// Every file at one depth before any deeper one
+    BreadthFirst,
+}
+
+/// Read inputs from stdin, one path or URL per line, keeping local files with
+/// an accepted extension and walking directories as `walk` says. Lines that
+/// name nothing usable go to `warn`, so the rest still run.
+pub fn collect_from_stdin(
+    exts: &[String],
+    walk: Walk,
+    warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    collect_from_lines(io::stdin().lock(), exts, walk, warn)
+}
+
+/// What [`collect_from_stdin`] does, for any reader.
+fn collect_from_lines(
+    reader: impl BufRead,
+    exts: &[String],
+    walk: Walk,
+    mut warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    let mut files = Vec::new();
+
+    for line in reader.lines() {
+        match inputs_from_line(&line?, exts, walk, &mut warn) {
+            Ok(inputs) => files.extend(inputs),
+            Err(e) => warn(e),
+        }
+    }
+
+    Ok(files)
+}
+
+/// The inputs named by one line of a file list, as for [`inputs_at`]. Blank
+/// lines give nothing.
+pub fn inputs_from_line(
+    line: &str,
+    exts: &[String],
+    walk: Walk,
+    warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(Vec::new());
+    }
+    inputs_at(Path::new(line), exts, walk, warn)
+}
+
+/// The inputs a path names, whether given as an argument or on stdin: a URL,
+/// an audio file, the audio files in a tar archive, or (when `walk.recursive`)
+/// in a directory. Files with other extensions give nothing. Parts of a
+/// directory that can't be read go to `warn`, and the walk carries on.
+fn inputs_at(
+    path: &Path,
+    exts: &[String],
+    walk: Walk,
+    warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    // URLs often carry no extension, so they are taken as given
+    if let Some(url) = path.to_str().filter(|p| is_url(p)) {
+        return Ok(vec![Input::Url(url.to_string())]);
+    }
+
+    if path.is_file() {
+        if is_archive(path) {
+            archive_entries(path, exts)
+        } else if is_audio_file(path, exts) {
+            Ok(vec![Input::File(path.to_path_buf())])
+        } else {
+            Ok(Vec::new())
+        }
+    } else if path.is_dir() {
+        if walk.recursive {
+            Ok(audio_files_in(path, exts, walk, warn))
+        } else {
+            Err(AudioMetaError::IsDirectory(path.to_path_buf()))
+        }
+    } else {
+        Err(AudioMetaError::NotFound(path.to_path_buf()))
+    }
+}
+
+/// Every file below `dir` with an accepted extension, in `walk` order.
+/// Unreadable directories and entries go to `warn`.
+fn audio_files_in(
+    dir: &Path,
+    exts: &[String],
+    walk: Walk,
+    mut warn: impl FnMut(AudioMetaError),
+) -> Vec<Input> {
+    let files: Vec<PathBuf> = match walk.order {
+        WalkOrder::DepthFirst => {
+            let walker = walkdir::WalkDir::new(dir);
+            let walker = if walk.sorted {
+                walker.sort_by_file_name()
+            } else {
+                walker
+            };
+            let mut files = Vec::new();
+            for entry in walker {
+                match entry {
+                    Ok(entry) if entry.file_type().is_file() => files.push(entry.into_path()),
+                    Ok(_) => {}
+                    Err(e) => {
+                        let path = e.path().unwrap_or(dir).to_path_buf();
+                        let error = e
+                            .into_io_error()
+                            .unwrap_or_else(|| io::Error::other("filesystem loop"));
+                        warn(AudioMetaError::Walk(path, error));
+                    }
+                }
+            }
+            files
+        }
+        WalkOrder::BreadthFirst => breadth_first_files(dir, walk.sorted, warn),
+    };
+    files
+        .into_iter()
+        .filter(|p| is_audio_file(p, exts))
+        .map(Input::File)
+        .collect()
+}
+
+/// Every file below `dir`, shallowest first. Like walkdir, symlinks are not
+/// followed, and unreadable directories go to `warn` and are skipped.
+fn breadth_first_files(
+    dir: &Path,
+    sorted: bool,
+    mut warn: impl FnMut(AudioMetaError),
+) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut queue = VecDeque::from([dir.to_path_buf()]);
+
+    while let Some(dir) = queue.pop_front() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn(AudioMetaError::Walk(dir, e));
+                continue;
+            }
+        };
+        let mut entries: Vec<_> = entries
+            .filter_map(|e| {
+                e.map_err(|e| warn(AudioMetaError::Walk(dir.clone(), e)))
+                    .ok()
+            })
+            .collect();
+        if sorted {
+            entries.sort_by_key(|e| e.file_name());
+        }
+        for entry in entries {
+            match entry.file_type() {
+                Ok(kind) if kind.is_file() => files.push(entry.path()),
+                Ok(kind) if kind.is_dir() => queue.push_back(entry.path()),
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// Expand command-line paths into inputs, walking directories as `walk`
+/// says. Paths given directly keep their order; unreadable parts of a
+/// directory go to `warn`.
+pub fn collect_audio_files(
+    paths: &[PathBuf],
+    exts: &[String],
+    walk: Walk,
+    mut warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        files.extend(inputs_at(path, exts, walk, &mut warn)?);
+    }
+
+    Ok(files)
+}
+
+/// Whether `path` has one of the accepted extensions (compared case-insensitively).
+pub fn is_audio_file(path: &Path, exts: &[String]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext_str| {
+            exts.iter()
+                .any(|accepted| accepted.eq_ignore_ascii_case(ext_str))
+        })
+        .unwrap_or(false)
+}
+
+fn ebur128_channel(channel: Channels) -> ebur128::Channel {
+    match channel {
+        Channels::FRONT_LEFT => ebur128::Channel::Left,
+        Channels::FRONT_RIGHT => ebur128::Channel::Right,
+        Channels::FRONT_CENTRE => ebur128::Channel::Center,
+        Channels::REAR_LEFT | Channels::SIDE_LEFT => ebur128::Channel::LeftSurround,
+        Channels::REAR_RIGHT | Channels::SIDE_RIGHT => ebur128::Channel::RightSurround,
+        Channels::LFE1 | Channels::LFE2 => ebur128::Channel::Unused,
+        // Heights and wide channels have no BS.1770 weighting; count them at unity
+        _ => ebur128::Channel::Center,
+    }
+}
+
+/// What a decode pass found besides the audio itself.
+struct DecodeSummary {
+    /// Only decoders that support verification (FLAC) give a verdict
+    verify_ok: Option<bool>,
+    /// Packets the decoder rejected as corrupt and skipped
+    errors: usize,
+}
+
+/// Decode the packets of `track_id`, handing each decoded buffer to `sink` as
+/// interleaved f32 samples, until the track ends or `sink` breaks. Corrupt
+/// packets are counted and skipped; any other error ends the pass.
+fn decode_track(
+    format: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    params: &CodecParameters,
+    verify: bool,
+    mut sink: impl FnMut(&[f32]) -> Result<ControlFlow<()>, AudioMetaError>,
+) -> Result<DecodeSummary, AudioMetaError> {
+    let options = DecoderOptions { verify };
+    let mut decoder = symphonia::default::get_codecs().make(params, &options)?;
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    let mut errors = 0;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt packets are skipped, the same way a player would
+            Err(SymphoniaError::DecodeError(_)) => {
+                errors += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let buf = samples
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buf.copy_interleaved_ref(decoded);
+        if sink(buf.samples())?.is_break() {
+            break;
+        }
+    }
+
+    Ok(DecodeSummary {
+        verify_ok: decoder.finalize().verify_ok,
+        errors,
+    })
+}
+
+/// Decode packets of `track_id` up to the first good one, to report the
+/// buffers its decoder produces.
+fn decoder_output(
+    format: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    params: &CodecParameters,
+    info: &mut AudioInfo,
+) -> Result<(), AudioMetaError> {
+    let mut decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(());
+            }
+            Err(SymphoniaError::ResetRequired) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let sample_format = match decoded {
+            AudioBufferRef::U8(_) => "u8",
+            AudioBufferRef::U16(_) => "u16",
+            AudioBufferRef::U24(_) => "u24",
+            AudioBufferRef::U32(_) => "u32",
+            AudioBufferRef::S8(_) => "s8",
+            AudioBufferRef::S16(_) => "s16",
+            AudioBufferRef::S24(_) => "s24",
+            AudioBufferRef::S32(_) => "s32",
+            AudioBufferRef::F32(_) => "f32",
+            AudioBufferRef::F64(_) => "f64",
+        };
+        info.sample_format = Some(sample_format.to_string());
+        info.channel_order = decoded
+            .spec()
+            .channels
+            .iter()
+            .map(|channel| format!("{channel:?}").to_case(Case::Snake))
+            .collect();
+        info.max_frames_per_packet = params
+            .max_frames_per_packet
+            .or(Some(decoded.capacity() as u64));
+        return Ok(());
+    }
+}
+
+/// FFT size for spectral analysis.
+const SPECTRUM_WINDOW: usize = 4096;
+/// Roughly how many windows to analyze, spread evenly over the track.
+const SPECTRUM_WINDOWS: u64 = 64;
+/// How far below the spectral peak a band can sit and still count as content.
+const SPECTRUM_FLOOR_DB: f64 = 70.0;
+
+/// Averages the power spectrum of evenly spaced windows of a mono signal.
+struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    hann: Vec<f32>,
+    pending: Vec<f32>,
+    power: Vec<f64>,
+    stride: u64,
+    windows_seen: u64,
+    windows_analyzed: u64,
+}
+
+impl Spectrum {
+    fn new(n_frames: Option<u64>) -> Self {
+        let size = SPECTRUM_WINDOW;
+        let total_windows = n_frames.map_or(0, |n| n / size as u64);
+        Spectrum {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            hann: (0..size)
+                .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+                .collect(),
+            pending: Vec::with_capacity(size),
+            power: vec![0.0; size / 2 + 1],
+            stride: (total_windows / SPECTRUM_WINDOWS).max(1),
+            windows_seen: 0,
+            windows_analyzed: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.pending.push(sample);
+        if self.pending.len() < SPECTRUM_WINDOW {
+            return;
+        }
+
+        if self.windows_seen.is_multiple_of(self.stride) {
+            let mut buffer: Vec<Complex<f32>> = self
+                .pending
+                .iter()
+                .zip(&self.hann)
+                .map(|(s, w)| Complex::new(s * w, 0.0))
+                .collect();
+            self.fft.process(&mut buffer);
+            for (power, bin) in self.power.iter_mut().zip(&buffer) {
+                *power += f64::from(bin.norm_sqr());
+            }
+            self.windows_analyzed += 1;
+        }
+        self.windows_seen += 1;
+        self.pending.clear();
+    }
+
+    /// The averaged level of each bin in dB, and the loudest of them.
+    fn levels(&self) -> Option<(Vec<f64>, f64)> {
+        if self.windows_analyzed == 0 {
+            return None;
+        }
+
+        let db: Vec<f64> = self
+            .power
+            .iter()
+            .map(|p| 10.0 * (p / self.windows_analyzed as f64).max(1e-30).log10())
+            .collect();
+        let peak = db.iter().copied().fold(f64::MIN, f64::max);
+        // Digital silence has no meaningful spectrum
+        (peak >= -200.0).then_some((db, peak))
+    }
+
+    /// The highest frequency whose averaged level is within `SPECTRUM_FLOOR_DB`
+    /// of the spectral peak, ignoring isolated spikes.
+    fn cutoff_hz(&self, sample_rate: u32) -> Option<f64> {
+        let (db, peak) = self.levels()?;
+        let threshold = peak - SPECTRUM_FLOOR_DB;
+        let bin_hz = f64::from(sample_rate) / SPECTRUM_WINDOW as f64;
+        (2..db.len())
+            .rev()
+            .find(|&i| db[i - 2..=i].iter().all(|&d| d > threshold))
+            .map(|i| i as f64 * bin_hz)
+    }
+
+    /// How many dB the level falls across `cutoff_hz`: the average over the
+    /// `RESAMPLE_BAND_HZ` below it less the average over the band above, past
+    /// a transition of a quarter of that. `None` with no room above.
+    fn drop_db(&self, sample_rate: u32, cutoff_hz: f64) -> Option<f64> {
+        let (db, _) = self.levels()?;
+        let bin_hz = f64::from(sample_rate) / SPECTRUM_WINDOW as f64;
+        let bin = |hz: f64| ((hz / bin_hz).round().max(0.0) as usize).min(db.len());
+        let mean =
+            |bins: &[f64]| (!bins.is_empty()).then(|| bins.iter().sum::<f64>() / bins.len() as f64);
+        let below = mean(&db[bin(cutoff_hz - RESAMPLE_BAND_HZ)..bin(cutoff_hz)])?;
+        let above =
+            mean(&db[bin(cutoff_hz + RESAMPLE_BAND_HZ / 4.0)..bin(cutoff_hz + RESAMPLE_BAND_HZ)])?;
+        Some(below - above)
+    }
+}
+
+/// Sample rates audio is commonly produced at, and so resampled from.
+const STANDARD_RATES: &[u32] = &[
+    8000, 11025, 16000, 22050, 24000, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+/// How far under a rate's Nyquist frequency a resampler's low-pass can start.
+const RESAMPLE_PASSBAND: f64 = 0.95;
+/// Width of the bands `Spectrum::drop_db` compares either side of the cutoff.
+const RESAMPLE_BAND_HZ: f64 = 1000.0;
+/// How far the level has to fall across the cutoff to count as a low-pass
+/// filter rather than the music running out.
+const RESAMPLE_DROP_DB: f64 = 30.0;
+/// Lossy encoders often low-pass below this themselves, so lower rates
+/// aren't suspected for them.
+const LOSSY_LOWPASS_HZ: f64 = 20_000.0;
+
+/// The standard rate below `sample_rate` whose Nyquist frequency a steep
+/// cutoff in `spectrum` sits just under, with the cutoff and how steep it is.
+/// `None` when there is no spectrum to judge.
+fn resampled_from(
+    spectrum: &Spectrum,
+    sample_rate: u32,
+    lossy: bool,
+) -> Option<Option<(u32, f64, f64)>> {
+    let cutoff = spectrum.cutoff_hz(sample_rate)?;
+    let Some(drop) = spectrum.drop_db(sample_rate, cutoff) else {
+        return Some(None);
+    };
+    let from = STANDARD_RATES
+        .iter()
+        .copied()
+        .filter(|&rate| rate < sample_rate)
+        .filter(|&rate| !lossy || f64::from(rate) / 2.0 >= LOSSY_LOWPASS_HZ)
+        .find(|&rate| {
+            let nyquist = f64::from(rate) / 2.0;
+            // A bin's width of slack above, for where the cutoff lands in the FFT
+            let slack = f64::from(sample_rate) / SPECTRUM_WINDOW as f64;
+            (nyquist * RESAMPLE_PASSBAND..=nyquist + slack).contains(&cutoff)
+        });
+    Some(
+        from.filter(|_| drop >= RESAMPLE_DROP_DB)
+            .map(|rate| (rate, cutoff, drop)),
+    )
+}
+
+/// FFT size for onset detection.
+const TEMPO_WINDOW: usize = 1024;
+/// Samples between onset detection windows.
+const TEMPO_HOP: usize = 512;
+/// The range of tempos `Tempo` considers.
+const TEMPO_MIN_BPM: f64 = 60.0;
+const TEMPO_MAX_BPM: f64 = 200.0;
+/// Tempo the estimate leans towards when picking between octaves.
+const TEMPO_PRIOR_BPM: f64 = 120.0;
+
+/// Estimates the tempo of a mono signal from the periodicity of its onsets,
+/// found as the spectral flux between overlapping windows.
+struct Tempo {
+    fft: Arc<dyn Fft<f32>>,
+    hann: Vec<f32>,
+    pending: Vec<f32>,
+    previous: Vec<f32>,
+    flux: Vec<f32>,
+}
+
+impl Tempo {
+    fn new() -> Self {
+        let size = TEMPO_WINDOW;
+        Tempo {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            hann: (0..size)
+                .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+                .collect(),
+            pending: Vec::with_capacity(size),
+            previous: vec![0.0; size / 2 + 1],
+            flux: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.pending.push(sample);
+        if self.pending.len() < TEMPO_WINDOW {
+            return;
+        }
+
+        let mut buffer: Vec<Complex<f32>> = self
+            .pending
+            .iter()
+            .zip(&self.hann)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+        let mut flux = 0.0;
+        for (previous, bin) in self.previous.iter_mut().zip(&buffer) {
+            // Log compression keeps loud sustained notes from drowning out onsets
+            let magnitude = (1.0 + 1000.0 * bin.norm()).ln();
+            flux += (magnitude - *previous).max(0.0);
+            *previous = magnitude;
+        }
+        self.flux.push(flux);
+        self.pending.drain(..TEMPO_HOP);
+    }
+
+    /// The tempo whose beat period best matches the onset envelope's
+    /// autocorrelation, weighted towards `TEMPO_PRIOR_BPM` to settle octave
+    /// ambiguity. `None` for silence or too little audio to tell.
+    fn bpm(&self, sample_rate: u32) -> Option<f64> {
+        let frame_rate = f64::from(sample_rate) / TEMPO_HOP as f64;
+        let min_lag = (60.0 * frame_rate / TEMPO_MAX_BPM).ceil() as usize;
+        let max_lag = (60.0 * frame_rate / TEMPO_MIN_BPM).floor() as usize;
+        // Ask for a few beats even at the slowest tempo
+        if min_lag == 0 || self.flux.len() < max_lag * 4 {
+            return None;
+        }
+
+        let mean = self.flux.iter().map(|&f| f64::from(f)).sum::<f64>() / self.flux.len() as f64;
+        let envelope: Vec<f64> = self.flux.iter().map(|&f| f64::from(f) - mean).collect();
+        let score = |lag: usize| -> f64 {
+            let correlation = envelope
+                .iter()
+                .zip(&envelope[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f64>()
+                / (envelope.len() - lag) as f64;
+            let octaves = (60.0 * frame_rate / lag as f64 / TEMPO_PRIOR_BPM).log2();
+            correlation * (-0.5 * octaves * octaves).exp()
+        };
+        let scores: Vec<f64> = (min_lag - 1..=max_lag + 1).map(score).collect();
+        let (best, &peak) = scores[1..scores.len() - 1]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+        if peak <= 0.0 {
+            return None;
+        }
+
+        // Interpolate between lags for a tempo finer than one hop
+        let (before, after) = (scores[best], scores[best + 2]);
+        let curvature = before - 2.0 * peak + after;
+        let offset = if curvature < 0.0 {
+            0.5 * (before - after) / curvature
+        } else {
+            0.0
+        };
+        let lag = (min_lag + best) as f64 + offset;
+        Some((600.0 * frame_rate / lag).round() / 10.0)
+    }
+}
+
+/// Frames per second `Classifier` splits audio into (20 ms each).
+const CLASSIFY_FRAME_RATE: u32 = 50;
+/// How much audio `Classifier` looks at, from a third of the way in.
+const CLASSIFY_SECONDS: u32 = 20;
+/// The least audio that gets a verdict.
+const CLASSIFY_MIN_SECONDS: u32 = 2;
+/// RMS level of a frame below which it counts as silence (about -80 dBFS).
+const CLASSIFY_SILENCE_RMS: f64 = 1e-4;
+
+/// Tells speech from music by how their frames vary: speech pauses between
+/// syllables (many quiet frames), alternates voiced and unvoiced sounds
+/// (zero-crossing rate spikes) and moves its spectral centroid around more.
+struct Classifier {
+    fft: Arc<dyn Fft<f32>>,
+    hann: Vec<f32>,
+    pending: Vec<f32>,
+    bin_hz: f64,
+    /// Per frame: RMS level, zero crossings per sample, spectral centroid in Hz
+    frames: Vec<(f64, f64, f64)>,
+}
+
+impl Classifier {
+    fn new(sample_rate: u32) -> Self {
+        let size = (sample_rate / CLASSIFY_FRAME_RATE).max(16) as usize;
+        Classifier {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            hann: (0..size)
+                .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / size as f32).cos())
+                .collect(),
+            pending: Vec::with_capacity(size),
+            bin_hz: f64::from(sample_rate) / size as f64,
+            frames: Vec::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.frames.len() >= (CLASSIFY_SECONDS * CLASSIFY_FRAME_RATE) as usize
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.pending.push(sample);
+        if self.pending.len() < self.hann.len() {
+            return;
+        }
+
+        let size = self.pending.len() as f64;
+        let rms = (self
+            .pending
+            .iter()
+            .map(|&s| f64::from(s) * f64::from(s))
+            .sum::<f64>()
+            / size)
+            .sqrt();
+        let crossings = self
+            .pending
+            .windows(2)
+            .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+            .count();
+        let mut buffer: Vec<Complex<f32>> = self
+            .pending
+            .iter()
+            .zip(&self.hann)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+        let magnitudes = buffer[..buffer.len() / 2 + 1]
+            .iter()
+            .map(|bin| f64::from(bin.norm()));
+        let (weighted, total) = magnitudes
+            .enumerate()
+            .fold((0.0, 0.0), |(weighted, total), (i, m)| {
+                (weighted + i as f64 * self.bin_hz * m, total + m)
+            });
+        let centroid = if total > 0.0 { weighted / total } else { 0.0 };
+        self.frames.push((rms, crossings as f64 / size, centroid));
+        self.pending.clear();
+    }
+
+    /// `speech`, `music` or `unknown`, with the measurements behind it.
+    fn content_type(&self) -> (&'static str, String) {
+        let seconds = self.frames.len() as f64 / f64::from(CLASSIFY_FRAME_RATE);
+        let sounding: Vec<_> = self
+            .frames
+            .iter()
+            .filter(|frame| frame.0 >= CLASSIFY_SILENCE_RMS)
+            .collect();
+        if seconds < f64::from(CLASSIFY_MIN_SECONDS) || sounding.len() * 10 < self.frames.len() {
+            return (
+                "unknown",
+                format!("too little sound to judge in {seconds:.1}s"),
+            );
+        }
+
+        let mean = |values: &mut dyn Iterator<Item = f64>| {
+            let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+            sum / f64::from(n.max(1))
+        };
+        // Levels and zero-crossing rates are compared within each second, so
+        // a crescendo or a change of instrument doesn't count as a pause
+        let (mut quiet, mut hissy, mut total) = (0, 0, 0);
+        for second in self.frames.chunks(CLASSIFY_FRAME_RATE as usize) {
+            let sounding: Vec<_> = second
+                .iter()
+                .filter(|frame| frame.0 >= CLASSIFY_SILENCE_RMS)
+                .collect();
+            let mean_rms = mean(&mut second.iter().map(|frame| frame.0));
+            let mean_zcr = mean(&mut sounding.iter().map(|frame| frame.1));
+            // Pauses: frames well below the second's average level, silent ones included
+            quiet += second
+                .iter()
+                .filter(|frame| frame.0 < 0.5 * mean_rms)
+                .count();
+            // Unvoiced sounds: frames crossing zero far more often than the second's average
+            hissy += sounding
+                .iter()
+                .filter(|frame| frame.1 > 1.5 * mean_zcr)
+                .count();
+            total += second.len();
+        }
+        let quiet = quiet as f64 / total as f64;
+        let hissy = hissy as f64 / total as f64;
+        let mean_centroid = mean(&mut sounding.iter().map(|frame| frame.2));
+        let spread = mean(
+            &mut sounding
+                .iter()
+                .map(|frame| (frame.2 - mean_centroid).powi(2)),
+        )
+        .sqrt();
+        let variation = if mean_centroid > 0.0 {
+            spread / mean_centroid
+        } else {
+            0.0
+        };
+
+        // Pauses are the surest sign of speech, so it takes them and one more
+        let (paused, unvoiced, moving) = (quiet > 0.2, hissy > 0.1, variation > 0.5);
+        let verdict = if paused && (unvoiced || moving) {
+            "speech"
+        } else if !paused && !unvoiced && !moving {
+            "music"
+        } else {
+            "unknown"
+        };
+        let how = format!(
+            "{:.0}% quiet frames, {:.0}% high zero-crossing frames, spectral centroid variation {variation:.2}, \
+             over {seconds:.1}s",
+            quiet * 100.0,
+            hissy * 100.0
+        );
+        (verdict, how)
+    }
+}
+
+/// Decode up to `CLASSIFY_SECONDS` of the track, from a third of the way
+/// in to skip intros, and classify it as speech or music.
+fn classify_audio(
+    format: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    params: &CodecParameters,
+    options: &Options,
+    info: &mut AudioInfo,
+) -> Result<(), AudioMetaError> {
+    let n_channels = params
+        .channels
+        .ok_or(AudioMetaError::MissingParameter("channel layout"))?
+        .count();
+    let sample_rate = params
+        .sample_rate
+        .ok_or(AudioMetaError::MissingParameter("sample rate"))?;
+    if let Some(n_frames) = params.n_frames {
+        // A reader that can't seek just starts from the beginning
+        let _ = format.seek(
+            SeekMode::Coarse,
+            SeekTo::TimeStamp {
+                ts: n_frames / 3,
+                track_id,
+            },
+        );
+    }
+
+    let mut classifier = Classifier::new(sample_rate);
+    decode_track(format, track_id, params, false, |samples| {
+        for frame in samples.chunks_exact(n_channels) {
+            classifier.push(frame.iter().sum::<f32>() / n_channels as f32);
+        }
+        Ok(if classifier.is_full() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        })
+    })?;
+
+    let (verdict, how) = classifier.content_type();
+    info.content_type = Some(verdict.to_string());
+    info.explain(options.explain, "content_type", || how);
+    Ok(())
+}
+
+/// Run the opt-in decode-based measurements (loudness, peaks, spectrum,
+/// tempo, decode errors) over a track in a single pass.
+fn analyze_audio(
+    format: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    params: &CodecParameters,
+    options: &Options,
+    info: &mut AudioInfo,
+) -> Result<(), AudioMetaError> {
+    let channels = params
+        .channels
+        .ok_or(AudioMetaError::MissingParameter("channel layout"))?;
+    let sample_rate = params
+        .sample_rate
+        .ok_or(AudioMetaError::MissingParameter("sample rate"))?;
+    let n_channels = channels.count();
+
+    let mut meter = if options.loudness {
+        let mut meter = ebur128::EbuR128::new(
+            n_channels as u32,
+            sample_rate,
+            ebur128::Mode::I | ebur128::Mode::LRA,
+        )?;
+        // A lone channel is a mono programme, which BS.1770 counts once at unity gain
+        if n_channels > 1 {
+            let map: Vec<_> = channels.iter().map(ebur128_channel).collect();
+            meter.set_channel_map(&map)?;
+        }
+        Some(meter)
+    } else {
+        None
+    };
+    let mut peaks = options.peaks.then(|| vec![0.0f64; n_channels]);
+    let mut spectrum =
+        (options.spectral || options.resample_check).then(|| Spectrum::new(params.n_frames));
+    let mut tempo = (options.detect_bpm && info.bpm.is_none()).then(Tempo::new);
+    let mut frames = 0u64;
+
+    let summary = decode_track(format, track_id, params, options.verify_md5, |samples| {
+        frames += (samples.len() / n_channels) as u64;
+        if let Some(meter) = meter.as_mut() {
+            meter.add_frames_f32(samples)?;
+        }
+        if let Some(peaks) = peaks.as_mut() {
+            for frame in samples.chunks_exact(n_channels) {
+                for (peak, sample) in peaks.iter_mut().zip(frame) {
+                    *peak = peak.max(f64::from(sample.abs()));
+                }
+            }
+        }
+        if let Some(spectrum) = spectrum.as_mut() {
+            for frame in samples.chunks_exact(n_channels) {
+                spectrum.push(frame.iter().sum::<f32>() / n_channels as f32);
+            }
+        }
+        if let Some(tempo) = tempo.as_mut() {
+            for frame in samples.chunks_exact(n_channels) {
+                tempo.push(frame.iter().sum::<f32>() / n_channels as f32);
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+
+    if let Some(meter) = meter {
+        // Digital silence measures as -inf, which has no JSON representation
+        info.integrated_lufs = Some(meter.loudness_global()?).filter(|v| v.is_finite());
+        info.loudness_range_lu = Some(meter.loudness_range()?).filter(|v| v.is_finite());
+    }
+    if let Some(peaks) = peaks {
+        info.sample_peak = peaks.iter().copied().reduce(f64::max);
+        info.channel_peaks = peaks;
+    }
+    if let Some(spectrum) = spectrum {
+        if options.spectral {
+            info.frequency_cutoff_hz = spectrum.cutoff_hz(sample_rate);
+        }
+        if options.resample_check {
+            let from = resampled_from(&spectrum, sample_rate, !is_lossless(params));
+            info.likely_resampled = from.map(|from| from.is_some());
+            if let Some(Some((rate, cutoff, drop))) = from {
+                info.warnings.push(format!(
+                    "steep low-pass at {cutoff:.0} Hz, as if resampled from {rate} Hz"
+                ));
+                info.explain(options.explain, "likely_resampled", || {
+                    format!("level falls {drop:.0} dB across {cutoff:.0} Hz, just under {rate} Hz's Nyquist frequency")
+                });
+            }
+        }
+    }
+    if let Some(tempo) = tempo {
+        info.bpm = tempo.bpm(sample_rate);
+        info.bpm_estimated = info.bpm.map(|_| true);
+        if info.bpm.is_some() {
+            info.explain(options.explain, "bpm", || {
+                format!("estimated: autocorrelation of spectral-flux onsets, {TEMPO_MIN_BPM}-{TEMPO_MAX_BPM} BPM")
+            });
+        }
+    }
+    info.flac_md5_ok = summary.verify_ok;
+    info.decode_errors = Some(summary.errors);
+    if summary.errors > 0 {
+        info.warnings.push(format!(
+            "skipped {} corrupt packet(s) while decoding",
+            summary.errors
+        ));
+    }
+
+    if options.verify_duration {
+        let decoded = frames as f64 / f64::from(sample_rate);
+        info.decoded_duration_seconds = Some(decoded);
+        if let Some(declared) = info.declared_duration_seconds
+            && (declared - decoded).abs() > DURATION_TOLERANCE_SECONDS
+        {
+            info.warnings.push(format!(
+                "header declares {declared:.3}s of audio but {decoded:.3}s decoded"
+            ));
+        }
+        // The decoded length wins over a header that is wrong or missing
+        info.duration_seconds = Some(decoded as u64);
+        info.avg_bitrate_kbps = avg_bitrate_kbps(info.file_size_bytes, decoded);
+        info.explain(options.explain, "duration_seconds", || {
+            format!("frames decoded / sample_rate, over the header ({frames} / {sample_rate} Hz = {decoded:.3}s)")
+        });
+        let file_size = info.file_size_bytes;
+        info.explain(options.explain, "avg_bitrate_kbps", || {
+            format!("file_size_bytes * 8 / decoded duration, whole file ({file_size} * 8 / {decoded:.3}s)")
+        });
+    }
+
+    Ok(())
+}
+
+/// Whole-file size over `seconds`, in kbit/s. `None` for a zero or
+/// non-finite duration, and for a rate too large to be real audio rather than
+/// one saturated to `u32::MAX`.
+fn avg_bitrate_kbps(file_size: u64, seconds: f64) -> Option<u32> {
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return None;
+    }
+    // Bits as u128 can't overflow; as f64 they stay exact to 2^53 bits (2^50 bytes, 1 PiB)
+    let kbps = (u128::from(file_size) * 8) as f64 / seconds / 1_000.0;
+    (kbps <= f64::from(u32::MAX)).then_some(kbps as u32)
+}
+
+/// How far the declared and decoded durations may drift apart before
+/// `verify_duration` warns, allowing for encoder delay and padding.
+const DURATION_TOLERANCE_SECONDS: f64 = 0.1;
+
+/// Codecs symphonia can identify but has no decoder (and so no name) for.
+const UNDECODABLE_CODECS: &[(CodecType, &str)] = &[
+    (codecs::CODEC_TYPE_OPUS, "opus"),
+    (codecs::CODEC_TYPE_SPEEX, "speex"),
+    (codecs::CODEC_TYPE_EAC3, "eac3"),
+    (codecs::CODEC_TYPE_AC4, "ac4"),
+    (codecs::CODEC_TYPE_DCA, "dca"),
+    (codecs::CODEC_TYPE_WAVPACK, "wavpack"),
+    (codecs::CODEC_TYPE_MONKEYS_AUDIO, "ape"),
+    (codecs::CODEC_TYPE_TTA, "tta"),
+];
+
+/// Symphonia's short name for a codec (e.g. `flac`, `pcm_s16le`), falling
+/// back to its numeric id.
+fn codec_name(params: &CodecParameters) -> String {
+    if let Some(codec) = symphonia::default::get_codecs().get_codec(params.codec) {
+        return codec.short_name.to_string();
+    }
+    UNDECODABLE_CODECS
+        .iter()
+        .find(|(codec, _)| *codec == params.codec)
+        .map_or_else(|| params.codec.to_string(), |(_, name)| name.to_string())
+}
+
+/// Lossless codecs other than plain PCM.
+const LOSSLESS_CODECS: &[CodecType] = &[
+    codecs::CODEC_TYPE_FLAC,
+    codecs::CODEC_TYPE_ALAC,
+    codecs::CODEC_TYPE_WAVPACK,
+    codecs::CODEC_TYPE_MONKEYS_AUDIO,
+    codecs::CODEC_TYPE_TTA,
+];
+
+/// Whether the codec keeps every sample. Companded PCM (A-law, mu-law) isn't
+/// lossless from its source.
+fn is_lossless(params: &CodecParameters) -> bool {
+    let name = codec_name(params);
+    let pcm = name.starts_with("pcm_") && !matches!(name.as_str(), "pcm_alaw" | "pcm_mulaw");
+    pcm || LOSSLESS_CODECS.contains(&params.codec)
+}
+
+/// `file_size` over the size of the track as raw PCM, with the sum behind it.
+/// Only lossless codecs get one.
+fn compression_ratio(params: &CodecParameters, file_size: u64) -> Option<(f64, String)> {
+    if !is_lossless(params) {
+        return None;
+    }
+
+    let bits = params.bits_per_sample.or(params.bits_per_coded_sample)?;
+    let channels = params.channels?.count();
+    // n_frames counts time base units, which Matroska keeps in milliseconds
+    let duration = params.time_base?.calc_time(params.n_frames?);
+    let frames = (duration.seconds as f64 + duration.frac) * f64::from(params.sample_rate?);
+    let pcm_bytes = frames * channels as f64 * f64::from(bits) / 8.0;
+    (pcm_bytes > 0.0).then(|| {
+        let how = format!(
+            "file_size_bytes / raw PCM size ({file_size} / ({frames:.0} frames * {channels} channels * {bits} bits / 8))"
+        );
+        (file_size as f64 / pcm_bytes, how)
+    })
+}
+
+/// Name of the format for extensions we accept but symphonia has no reader for.
+fn unsupported_format_name(ext: Option<&str>) -> Option<&'static str> {
+    match ext?.to_lowercase().as_str() {
+        "tak" => Some("TAK"),
+        "mpc" => Some("Musepack"),
+        "wma" => Some("Windows Media Audio"),
+        _ => None,
+    }
+}
+
+fn probe(
+    mss: MediaSourceStream,
+    hint: &Hint,
+    ext: Option<&str>,
+) -> Result<ProbeResult, AudioMetaError> {
+    symphonia::default::get_probe()
+        .format(
+            hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| match (e, unsupported_format_name(ext)) {
+            (SymphoniaError::Unsupported(_), Some(name)) => AudioMetaError::Unsupported(name),
+            (e, _) => AudioMetaError::Probe(e),
+        })
+}
+
+/// How much of the start of a file to read for header details.
+const HEAD_BYTES: u64 = 64 * 1024;
+
+/// Read a little-endian u32 length followed by that many bytes of UTF-8.
+fn read_le_string(bytes: &[u8]) -> Option<String> {
+    let len = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let raw = bytes.get(4..4 + len)?;
+    Some(String::from_utf8_lossy(raw).into_owned())
+}
+
+/// A tag value as text that is safe to print: NUL padding (common in RIFF
+/// INFO chunks) is dropped and other control characters except line breaks
+/// and tabs are escaped as `\u{..}`, so they can't drive a terminal or break
+/// a CSV row.
+fn tag_text(value: &Value) -> String {
+    let text = value.to_string();
+    let mut safe = String::with_capacity(text.len());
+    for c in text.trim_end_matches('\0').chars() {
+        if c.is_control() && !matches!(c, '\n' | '\r' | '\t') {
+            safe.extend(c.escape_unicode());
+        } else {
+            safe.push(c);
+        }
+    }
+    safe
+}
+
+/// Whether a text tag holds binary data: a picture some taggers store as
+/// base64 text (Vorbis `COVERART`, or a `METADATA_BLOCK_PICTURE` symphonia
+/// couldn't parse), or text that is mostly control characters.
+fn is_binary_text(key: &str, text: &str) -> bool {
+    let key = key.rsplit(':').next().unwrap_or(key).to_ascii_lowercase();
+    if matches!(key.as_str(), "coverart" | "metadata_block_picture") {
+        return true;
+    }
+    let text = text.trim_end_matches('\0');
+    let unprintable = text
+        .chars()
+        .filter(|&c| {
+            (c.is_control() && !matches!(c, '\n' | '\r' | '\t')) || c == char::REPLACEMENT_CHARACTER
+        })
+        .count();
+    unprintable * 10 > text.chars().count()
+}
+
+/// The first non-empty value of a tag with one of `std_keys`, or whose raw
+/// key (after any `namespace:` prefix, lowercased, letters and digits only)
+/// is one of `names`.
+fn identifier(tags: &[Tag], std_keys: &[StandardTagKey], names: &[&str]) -> Option<String> {
+    let compact = |key: &str| -> String {
+        let key = key.rsplit(':').next().unwrap_or(key);
+        key.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase()
+    };
+    tags.iter()
+        .filter(|tag| {
+            tag.std_key.is_some_and(|key| std_keys.contains(&key))
+                || names.contains(&compact(&tag.key).as_str())
+        })
+        .map(|tag| tag_text(&tag.value).trim().to_string())
+        .find(|value| !value.is_empty())
+}
+
+/// An ISRC in its compact form (`CCXXXYYNNNNN`), accepting the usual
+/// hyphens and spaces and any case. `None` unless it has a two-letter
+/// country code, a three-character registrant code and seven digits.
+fn normalize_isrc(value: &str) -> Option<String> {
+    let isrc: String = value
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let bytes = isrc.as_bytes();
+    let valid = bytes.len() == 12
+        && bytes[..2].iter().all(u8::is_ascii_alphabetic)
+        && bytes[2..5].iter().all(u8::is_ascii_alphanumeric)
+        && bytes[5..].iter().all(u8::is_ascii_digit);
+    valid.then_some(isrc)
+}
+
+/// Approximate size of a file's tags: every key and value, plus the
+/// pictures.
+fn tag_value_bytes(tags: &[Tag], art: &[Artwork]) -> u64 {
+    let values: usize = tags
+        .iter()
+        .map(|tag| {
+            tag.key.len()
+                + match &tag.value {
+                    Value::Binary(data) => data.len(),
+                    value => value.to_string().len(),
+                }
+        })
+        .sum();
+    let pictures: usize = art.iter().map(|art| art.size_bytes).sum();
+    (values + pictures) as u64
+}
+
+/// Collapse tags with the same key into one entry, kept where the key first
+/// appeared.
+fn dedupe_tags(tags: &mut Vec<(String, String)>, mode: DuplicateTags) {
+    if mode == DuplicateTags::Keep {
+        return;
+    }
+
+    let mut merged: Vec<(String, String)> = Vec::with_capacity(tags.len());
+    for (key, value) in tags.drain(..) {
+        let Some((_, kept)) = merged.iter_mut().find(|(k, _)| *k == key) else {
+            merged.push((key, value));
+            continue;
+        };
+        match mode {
+            DuplicateTags::Keep | DuplicateTags::First => {}
+            DuplicateTags::Last => *kept = value,
+            DuplicateTags::Join => {
+                if !kept.split("; ").any(|v| v == value) {
+                    kept.push_str("; ");
+                    kept.push_str(&value);
+                }
+            }
+        }
+    }
+    *tags = merged;
+}
+
+/// Decode a 28-bit ID3v2 "syncsafe" integer.
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &b| size << 7 | usize::from(b & 0x7f))
+}
+
+/// The full length of an ID3v2 tag at the start of `head`, header and any
+/// footer included.
+fn id3v2_len(head: &[u8]) -> Option<usize> {
+    let header = head.get(..10).filter(|h| h.starts_with(b"ID3"))?;
+    // Bit 4 of the flags marks a 10-byte footer
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + syncsafe(&header[6..10]) + footer)
+}
+
+/// Skip an ID3v2 tag at the start of `head`, if there is one.
+fn skip_id3v2(head: &[u8]) -> &[u8] {
+    match id3v2_len(head) {
+        Some(len) => head.get(len..).unwrap_or_default(),
+        None => head,
+    }
+}
+
+/// Split a string in an ID3v2 text encoding off the front of `bytes`,
+/// returning it and the bytes after its terminator.
+fn id3v2_string(bytes: &[u8], encoding: u8) -> (String, &[u8]) {
+    if matches!(encoding, 1 | 2) {
+        let end = bytes
+            .chunks_exact(2)
+            .position(|c| c == [0, 0])
+            .map_or(bytes.len(), |i| i * 2);
+        let mut units: Vec<u16> = bytes[..end]
+            .chunks_exact(2)
+            .map(|c| match encoding {
+                2 => u16::from_be_bytes([c[0], c[1]]),
+                _ => u16::from_le_bytes([c[0], c[1]]),
+            })
+            .collect();
+        match units.first() {
+            // A byte-swapped BOM means the text is big-endian
+            Some(0xfffe) => units = units[1..].iter().map(|u| u.swap_bytes()).collect(),
+            Some(0xfeff) => {
+                units.remove(0);
+            }
+            _ => {}
+        }
+        (
+            String::from_utf16_lossy(&units),
+            bytes.get(end + 2..).unwrap_or_default(),
+        )
+    } else {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        let text = match encoding {
+            0 => bytes[..end].iter().map(|&b| char::from(b)).collect(),
+            _ => String::from_utf8_lossy(&bytes[..end]).into_owned(),
+        };
+        (text, bytes.get(end + 1..).unwrap_or_default())
+    }
+}
+
+/// Convert the first millisecond-timed SYLT frame of an ID3v2.3/2.4 tag to
+/// LRC, one `[mm:ss.xx]` line per synchronized text entry.
+fn synced_lyrics(tag: &[u8]) -> Option<String> {
+    let header = tag.get(..10).filter(|h| h.starts_with(b"ID3"))?;
+    let version = header[3];
+    // v2.2 uses three-letter frame IDs, and unsynchronized tags would need
+    // undoing first; neither is worth it for lyrics
+    if !matches!(version, 3 | 4) || header[5] & 0x80 != 0 {
+        return None;
+    }
+    let end = (10 + syncsafe(&header[6..10])).min(tag.len());
+
+    let mut pos = 10;
+    if header[5] & 0x40 != 0 {
+        let size = tag.get(10..14)?;
+        pos += match version {
+            3 => 4 + u32::from_be_bytes(size.try_into().ok()?) as usize,
+            _ => syncsafe(size),
+        };
+    }
+
+    while pos + 10 <= end {
+        let frame = &tag[pos..pos + 10];
+        if frame[0] == 0 {
+            // Padding
+            break;
+        }
+        let size = match version {
+            3 => u32::from_be_bytes(frame[4..8].try_into().ok()?) as usize,
+            _ => syncsafe(&frame[4..8]),
+        };
+        let body = tag.get(pos + 10..(pos + 10 + size).min(end))?;
+        pos += 10 + size;
+
+        // Encoding, language, timestamp format (2 = milliseconds), content type
+        if &frame[..4] != b"SYLT" || body.len() < 6 || body[4] != 2 {
+            continue;
+        }
+        let encoding = body[0];
+        let (_descriptor, mut rest) = id3v2_string(&body[6..], encoding);
+
+        let mut lrc = String::new();
+        while !rest.is_empty() {
+            let (text, after) = id3v2_string(rest, encoding);
+            let Some(stamp) = after.get(..4) else { break };
+            let ms = u32::from_be_bytes(stamp.try_into().ok()?);
+            rest = &after[4..];
+            lrc.push_str(&format!(
+                "[{:02}:{:02}.{:02}]{}\n",
+                ms / 60_000,
+                ms / 1000 % 60,
+                ms % 1000 / 10,
+                // SYLT marks new lines with a leading line break
+                text.trim_start_matches(['\n', '\r'])
+            ));
+        }
+        if !lrc.is_empty() {
+            return Some(lrc);
+        }
+    }
+    None
+}
+
+/// The AAC profile, from the AudioSpecificConfig the container carries (MP4,
+/// Matroska) or else the first ADTS header. HE-AAC only shows when the file
+/// signals it explicitly; streams that leave SBR implicit come out as `LC`.
+fn aac_profile(params: &CodecParameters, head: &[u8]) -> Option<String> {
+    if params.codec != codecs::CODEC_TYPE_AAC {
+        return None;
+    }
+    let object_type = match params.extra_data.as_deref() {
+        Some(config) if config.len() >= 2 => match config[0] >> 3 {
+            // 31 escapes to a 6-bit extension
+            31 => 32 + ((config[0] & 0x07) << 3 | config[1] >> 5),
+            object_type => object_type,
+        },
+        _ => {
+            let start = id3v2_len(head).unwrap_or(0);
+            let header = head.get(start..start + 3)?;
+            if header[0] != 0xff || header[1] & 0xf6 != 0xf0 {
+                return None;
+            }
+            // ADTS stores the object type minus one
+            (header[2] >> 6) + 1
+        }
+    };
+    let name = match object_type {
+        1 => "Main",
+        2 => "LC",
+        3 => "SSR",
+        4 => "LTP",
+        5 => "HE-AAC",
+        23 => "LD",
+        29 => "HE-AACv2",
+        39 => "ELD",
+        42 => "xHE-AAC",
+        other => return Some(format!("object type {other}")),
+    };
+    Some(name.to_string())
+}
+
+/// Name a LAME preset from the 11-bit preset field of the LAME tag.
+fn lame_preset_name(preset: u16, vbr_method: u8) -> Option<String> {
+    let name = match preset {
+        0 => return None,
+        8..=320 if matches!(vbr_method, 1 | 8) => format!("CBR {preset}"),
+        8..=320 => format!("ABR {preset}"),
+        410..=500 if preset.is_multiple_of(10) => format!("V{}", (500 - preset) / 10),
+        1000 => "r3mix".to_string(),
+        1001 => "standard".to_string(),
+        1002 => "extreme".to_string(),
+        1003 => "insane".to_string(),
+        1004 => "fast standard".to_string(),
+        1005 => "fast extreme".to_string(),
+        1006 => "medium".to_string(),
+        1007 => "fast medium".to_string(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The first frame of an MPEG Layer III stream, if `head` starts with one
+/// (after any ID3v2 tag).
+fn mp3_first_frame(head: &[u8]) -> Option<&[u8]> {
+    let frame = skip_id3v2(head);
+    let header = frame.get(..4)?;
+    (header[0] == 0xff && header[1] & 0xe0 == 0xe0 && header[1] & 0x06 == 0x02).then_some(frame)
+}
+
+/// The Xing/Info header in an MP3's first frame, from its magic on.
+fn xing_header(head: &[u8]) -> Option<&[u8]> {
+    let frame = mp3_first_frame(head)?;
+    let header = &frame[..4];
+    let mpeg1 = header[1] & 0x18 == 0x18;
+    let mono = header[3] >> 6 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let crc = if header[1] & 1 == 0 { 2 } else { 0 };
+
+    let xing = frame.get(4 + crc + side_info..)?;
+    (xing.starts_with(b"Xing") || xing.starts_with(b"Info")).then_some(xing)
+}
+
+/// The preset and VBR quality from the LAME tag that follows the Xing/Info
+/// header in an MP3's first frame.
+fn lame_tag(head: &[u8]) -> Option<(Option<String>, Option<u8>)> {
+    let xing = xing_header(head)?;
+    let flags = u32::from_be_bytes(xing.get(4..8)?.try_into().ok()?);
+    let mut pos = 8;
+    for (flag, len) in [(1, 4), (2, 4), (4, 100)] {
+        if flags & flag != 0 {
+            pos += len;
+        }
+    }
+    let quality = if flags & 8 != 0 {
+        let quality = u32::from_be_bytes(xing.get(pos..pos + 4)?.try_into().ok()?);
+        pos += 4;
+        Some(quality)
+    } else {
+        None
+    };
+
+    let lame = xing.get(pos..pos + 36)?;
+    if !lame.starts_with(b"LAME") && !lame.starts_with(b"L3.99") {
+        return None;
+    }
+    let vbr_method = lame[9] & 0x0f;
+    let preset = u16::from_be_bytes([lame[26], lame[27]]) & 0x07ff;
+
+    // LAME writes the quality as 100 - 10 * V - q (q being the -q setting)
+    let vbr_quality = quality
+        .filter(|_| (3..=6).contains(&vbr_method))
+        .and_then(|quality| u8::try_from(100u32.checked_sub(quality)? / 10).ok())
+        .filter(|v| *v <= 9);
+
+    Some((lame_preset_name(preset, vbr_method), vbr_quality))
+}
+
+/// Identify the container from its magic bytes at the start of the file.
+fn container_name(head: &[u8]) -> Option<&'static str> {
+    let magic = |at: usize, magic: &[u8]| head.get(at..at + magic.len()) == Some(magic);
+
+    if magic(0, b"fLaC") {
+        Some("flac")
+    } else if magic(0, b"OggS") {
+        Some("ogg")
+    } else if magic(0, b"RIFF") && magic(8, b"WAVE") {
+        Some("wav")
+    } else if magic(0, b"FORM") && (magic(8, b"AIFF") || magic(8, b"AIFC")) {
+        Some("aiff")
+    } else if magic(0, b"caff") {
+        Some("caf")
+    } else if magic(4, b"ftyp") {
+        Some("mp4")
+    } else if magic(0, b"\x1a\x45\xdf\xa3") {
+        // The EBML header's DocType tells WebM from general Matroska
+        let size_len = head.get(4)?.leading_zeros() as usize + 1;
+        // A zero first byte is no valid size, so there's no DocType to read
+        if size_len > 8 {
+            return Some("matroska");
+        }
+        let size = head
+            .get(5..4 + size_len)?
+            .iter()
+            .fold(u64::from(head[4]) & (0xff >> size_len), |size, &b| {
+                size << 8 | u64::from(b)
+            });
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| size.checked_add(4 + size_len));
+        let header = end.and_then(|end| head.get(..end)).unwrap_or(head);
+        if header.windows(4).any(|w| w == b"webm") {
+            Some("webm")
+        } else {
+            Some("matroska")
+        }
+    } else if magic(0, b"MAC ") {
+        Some("ape")
+    } else if magic(0, b"wvpk") {
+        Some("wavpack")
+    } else if magic(0, b"ID3") {
+        // ID3v2 is almost always in front of MPEG audio
+        Some("mpeg")
+    } else if head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0 {
+        // Frame sync; ADTS (AAC) uses layer bits 00, which MPEG audio doesn't
+        if head[1] & 0x06 == 0 {
+            Some("adts")
+        } else {
+            Some("mpeg")
+        }
+    } else {
+        None
+    }
+}
+
+/// How many top-level boxes or chunks to walk before giving up on a file.
+const MAX_CHUNKS: usize = 1024;
+
+/// The ids, offsets and sizes of the MP4 boxes (`riff: false`) or RIFF chunks
+/// (`riff: true`) between `start` and `end`, stopping at the first one that
+/// can't be read.
+fn top_level_chunks(
+    source: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+    riff: bool,
+) -> Vec<([u8; 4], u64, u64)> {
+    let mut chunks = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end && chunks.len() < MAX_CHUNKS {
+        let mut header = [0u8; 8];
+        if source.seek(SeekFrom::Start(pos)).is_err() || source.read_exact(&mut header).is_err() {
+            break;
+        }
+        let id: [u8; 4] = if riff { &header[..4] } else { &header[4..8] }
+            .try_into()
+            .unwrap();
+        let size = if riff {
+            // RIFF sizes leave out the header, and odd-sized chunks are padded
+            let size = u64::from(u32::from_le_bytes(header[4..8].try_into().unwrap()));
+            8 + size + size % 2
+        } else {
+            match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+                // A box of size 0 runs to the end of its parent
+                0 => end - pos,
+                // Size 1 means a 64-bit size follows the id
+                1 => {
+                    let mut large = [0u8; 8];
+                    if source.read_exact(&mut large).is_err() {
+                        break;
+                    }
+                    u64::from_be_bytes(large)
+                }
+                size => u64::from(size),
+            }
+        };
+        chunks.push((id, pos, size));
+        if size < 8 {
+            break;
+        }
+        pos = pos.saturating_add(size);
+    }
+    chunks
+}
+
+/// The type, offset and total size of each FLAC metadata block, read from
+/// the block headers so large pictures past the head are still counted.
+fn flac_blocks(source: &mut (impl Read + Seek), file_size: u64) -> Vec<(u8, u64, u64)> {
+    let mut blocks = Vec::new();
+    let mut pos = 4;
+    while pos + 4 <= file_size && blocks.len() < MAX_CHUNKS {
+        let mut header = [0u8; 4];
+        if source.seek(SeekFrom::Start(pos)).is_err() || source.read_exact(&mut header).is_err() {
+            break;
+        }
+        let size = 4 + u64::from(u32::from_be_bytes([0, header[1], header[2], header[3]]));
+        blocks.push((header[0] & 0x7f, pos, size));
+        // The last block has the top bit set
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+        pos += size;
+    }
+    blocks
+}
+
+/// The first child of an MP4 box with the given id.
+fn mp4_child(
+    source: &mut (impl Read + Seek),
+    (_, offset, size): ([u8; 4], u64, u64),
+    id: &[u8; 4],
+) -> Option<([u8; 4], u64, u64)> {
+    top_level_chunks(source, offset + 8, offset + size, false)
+        .into_iter()
+        .find(|(child, _, _)| child == id)
+}
+
+/// The sample table (`stbl`) box of each track in an MP4 file.
+fn mp4_sample_tables(source: &mut (impl Read + Seek), file_size: u64) -> Vec<([u8; 4], u64, u64)> {
+    let Some(moov) = top_level_chunks(source, 0, file_size, false)
+        .into_iter()
+        .find(|(id, _, _)| id == b"moov")
+    else {
+        return Vec::new();
+    };
+    top_level_chunks(source, moov.1 + 8, moov.1 + moov.2, false)
+        .into_iter()
+        .filter(|(id, _, _)| id == b"trak")
+        .filter_map(|trak| {
+            let mdia = mp4_child(source, trak, b"mdia")?;
+            let minf = mp4_child(source, mdia, b"minf")?;
+            mp4_child(source, minf, b"stbl")
+        })
+        .collect()
+}
+
+/// The offset, size and type of the first sample entry in a sample table.
+fn mp4_sample_entry(
+    source: &mut (impl Read + Seek),
+    stbl: ([u8; 4], u64, u64),
+) -> Option<([u8; 4], u64, u64)> {
+    let stsd = mp4_child(source, stbl, b"stsd")?;
+    // Version, flags and entry count, then the first entry's size and type
+    let mut entry = [0u8; 8];
+    source.seek(SeekFrom::Start(stsd.1 + 16)).ok()?;
+    source.read_exact(&mut entry).ok()?;
+    let size = u64::from(u32::from_be_bytes(entry[..4].try_into().unwrap()));
+    Some((entry[4..].try_into().unwrap(), stsd.1 + 16, size))
+}
+
+/// The DRM scheme protecting an MP4 file's audio, from the sample entry type
+/// of its tracks: `fairplay` (iTunes M4P), `audible` (AAX) or `cenc`
+/// (Common Encryption).
+fn mp4_protection(source: &mut (impl Read + Seek), file_size: u64) -> Option<&'static str> {
+    let mut brand = [0u8; 8];
+    source.seek(SeekFrom::Start(4)).ok()?;
+    source.read_exact(&mut brand).ok()?;
+    if &brand[..4] != b"ftyp" {
+        return None;
+    }
+
+    for stbl in mp4_sample_tables(source, file_size) {
+        match mp4_sample_entry(source, stbl).map(|entry| entry.0) {
+            Some(ref id) if id == b"drms" => return Some("fairplay"),
+            Some(ref id) if id == b"aavd" => return Some("audible"),
+            Some(ref id) if id == b"enca" => return Some("cenc"),
+            _ => {}
+        }
+    }
+    // Audible files announce themselves in the brand even when the entry is unusual
+    (&brand[4..] == b"aax ").then_some("audible")
+}
+
+/// Run `helper` on an encrypted file: the file goes to its stdin, the key (if
+/// any) in hex in `AUDIOMETA_KEY`, and the scheme in `AUDIOMETA_DRM`. What it
+/// writes to stdout is read as the decrypted file.
+fn decrypt_with_helper(
+    helper: &Path,
+    key: Option<&Key>,
+    scheme: &str,
+    mut source: Box<dyn MediaSource>,
+) -> Result<Vec<u8>, AudioMetaError> {
+    let mut encrypted = Vec::new();
+    source.read_to_end(&mut encrypted)?;
+
+    let mut command = Command::new(helper);
+    command
+        .env("AUDIOMETA_DRM", scheme)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(key) = key {
+        command.env(
+            "AUDIOMETA_KEY",
+            key.0.iter().map(|b| format!("{b:02x}")).collect::<String>(),
+        );
+    }
+    let mut child = command.spawn().map_err(|e| {
+        AudioMetaError::DecryptHelper(format!("cannot run {}: {e}", helper.display()))
+    })?;
+    // Written from another thread, so a helper that streams its output can't
+    // fill the stdout pipe while it waits for more input
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || stdin.write_all(&encrypted));
+    let output = child.wait_with_output()?;
+    // A helper may stop reading once it has what it needs, closing the pipe
+    // early; its exit status says whether it worked
+    let _ = writer.join();
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .map(str::trim);
+        return Err(AudioMetaError::DecryptHelper(match message {
+            Some(message) => message.to_string(),
+            None => format!("{} exited with {}", helper.display(), output.status),
+        }));
+    }
+    Ok(output.stdout)
+}
+
+/// The top-level chunks of a WAV file, with the sizes their headers record.
+fn riff_chunks(source: &mut (impl Read + Seek), head: &[u8], file_size: u64) -> Vec<RiffChunk> {
+    if container_name(head) != Some("wav") {
+        return Vec::new();
+    }
+    let chunks = top_level_chunks(source, 12, file_size, true);
+    chunks
+        .into_iter()
+        .filter_map(|(id, offset, _)| {
+            // The padding top_level_chunks counts in isn't part of the size
+            let mut size = [0u8; 4];
+            source.seek(SeekFrom::Start(offset + 4)).ok()?;
+            source.read_exact(&mut size).ok()?;
+            Some(RiffChunk {
+                id: String::from_utf8_lossy(&id).into_owned(),
+                size_bytes: u64::from(u32::from_le_bytes(size)),
+            })
+        })
+        .collect()
+}
+
+/// Find the tag blocks in a file, whether each comes before or after the
+/// audio, and their sizes: ID3v2 and APEv2 at either end, ID3v1 at the end,
+/// Vorbis comments (and FLAC pictures) at the start, and in MP4 and WAV files
+/// the `moov` box or the INFO/`id3 ` chunks placed around the audio data.
+fn metadata_blocks(
+    source: &mut (impl Read + Seek),
+    head: &[u8],
+    file_size: u64,
+) -> io::Result<Vec<MetadataBlock>> {
+    let block = |format: &str, at_end, size_bytes| MetadataBlock {
+        format: format.to_string(),
+        at_end,
+        size_bytes,
+    };
+    let mut blocks = Vec::new();
+
+    if let Some(len) = id3v2_len(head) {
+        blocks.push(block("id3v2", false, Some(len as u64)));
+    } else if let Some(size) = ape_tag_size(head) {
+        blocks.push(block("ape", false, Some(size)));
+    }
+
+    match container_name(head) {
+        Some("flac") => {
+            for (kind, _, size) in flac_blocks(source, file_size) {
+                match kind {
+                    FLAC_VORBIS_COMMENT => blocks.push(block("vorbis_comment", false, Some(size))),
+                    FLAC_PICTURE => blocks.push(block("flac_picture", false, Some(size))),
+                    _ => {}
+                }
+            }
+        }
+        // Ogg keeps its comment header ahead of the audio by design
+        Some("ogg") if vorbis_vendor(head).is_some() => {
+            blocks.push(block("vorbis_comment", false, None))
+        }
+        Some("mp4") => {
+            let boxes = top_level_chunks(source, 0, file_size, false);
+            let position = |id: &[u8; 4]| boxes.iter().position(|(b, _, _)| b == id);
+            if let Some(moov) = position(b"moov") {
+                let at_end = position(b"mdat").is_some_and(|mdat| mdat < moov);
+                // Most of moov is sample tables; the tags live in udta/meta
+                let (_, offset, size) = boxes[moov];
+                let tag_size = top_level_chunks(source, offset + 8, offset + size, false)
+                    .iter()
+                    .filter(|(id, _, _)| id == b"udta" || id == b"meta")
+                    .map(|(_, _, size)| size)
+                    .sum();
+                blocks.push(block("mp4", at_end, Some(tag_size)));
+            }
+        }
+        Some("wav") => {
+            let chunks = top_level_chunks(source, 12, file_size, true);
+            let data = chunks.iter().position(|(id, _, _)| id == b"data");
+            for (i, (id, offset, size)) in chunks.iter().enumerate() {
+                let format = match id {
+                    b"id3 " | b"ID3 " => "riff_id3",
+                    b"LIST" => {
+                        let mut kind = [0u8; 4];
+                        source.seek(SeekFrom::Start(offset + 8))?;
+                        if source.read_exact(&mut kind).is_err() || &kind != b"INFO" {
+                            continue;
+                        }
+                        "riff_info"
+                    }
+                    _ => continue,
+                };
+                blocks.push(block(
+                    format,
+                    data.is_some_and(|data| data < i),
+                    Some(*size),
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    // Trailing tags stack up as [APEv2 or ID3v2 with footer] [ID3v1]
+    let mut end = Vec::new();
+    source.seek(SeekFrom::Start(file_size.saturating_sub(256)))?;
+    source.read_to_end(&mut end)?;
+    let mut rest = end.as_slice();
+    if let Some(split) = rest.len().checked_sub(128)
+        && rest[split..].starts_with(b"TAG")
+    {
+        blocks.push(block("id3v1", true, Some(128)));
+        rest = &rest[..split];
+    }
+    if let Some(split) = rest.len().checked_sub(32)
+        && let Some(size) = ape_tag_size(&rest[split..])
+    {
+        blocks.push(block("ape", true, Some(size)));
+    } else if let Some(split) = rest.len().checked_sub(10)
+        && rest[split..].starts_with(b"3DI")
+    {
+        // Header, body and footer
+        let size = 20 + syncsafe(&rest[split + 6..split + 10]) as u64;
+        blocks.push(block("id3v2", true, Some(size)));
+    }
+
+    Ok(blocks)
+}
+
+/// The full size of an APEv2 tag from its 32-byte header or footer at the
+/// start of `bytes`. The recorded size leaves out the header, which tags
+/// flag when they have one.
+fn ape_tag_size(bytes: &[u8]) -> Option<u64> {
+    let preamble = bytes.get(..32).filter(|p| p.starts_with(b"APETAGEX"))?;
+    let size = u64::from(u32::from_le_bytes(preamble[12..16].try_into().ok()?));
+    let flags = u32::from_le_bytes(preamble[20..24].try_into().ok()?);
+    // A header is itself the start of the tag, so it is always there then
+    let has_header = flags & 0x8000_0000 != 0 || flags & 0x2000_0000 != 0;
+    Some(size + if has_header { 32 } else { 0 })
+}
+
+/// How precisely the format reader seeks to the middle of the track: it is
+/// `accurate` when an accurate seek lands at or before the requested
+/// timestamp, so decoding on reaches it exactly, and `coarse` when only a
+/// coarse seek works. `None` when the track's length is unknown, since there
+/// is no middle to aim at, and seeking to the start proves nothing.
+fn seek_accuracy(
+    format: &mut Box<dyn FormatReader>,
+    track_id: u32,
+    params: &CodecParameters,
+) -> Option<&'static str> {
+    let ts = params.n_frames? / 2;
+    Some(
+        match format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts, track_id }) {
+            Ok(seeked) if seeked.actual_ts <= seeked.required_ts => "accurate",
+            _ if format
+                .seek(SeekMode::Coarse, SeekTo::TimeStamp { ts, track_id })
+                .is_ok() =>
+            {
+                "coarse"
+            }
+            _ => "none",
+        },
+    )
+}
+
+/// Whether the file carries an index for seeking without scanning: a FLAC
+/// SEEKTABLE, an MP3 Xing or VBRI table of contents, MP4 sample tables or
+/// Matroska Cues. Ogg and ADTS have none by design; `None` for containers
+/// where a position follows from a constant rate (WAV, AIFF, ...).
+fn has_seek_index(
+    source: &mut (impl Read + Seek),
+    head: &[u8],
+    tail: &[u8],
+    file_size: u64,
+) -> Option<bool> {
+    match container_name(head)? {
+        // A large PICTURE block can push the SEEKTABLE past the head
+        "flac" => Some(
+            flac_blocks(source, file_size)
+                .iter()
+                .any(|&(kind, _, _)| kind == FLAC_SEEKTABLE),
+        ),
+        "mpeg" => {
+            let toc = xing_header(head)
+                .and_then(|xing| xing.get(4..8))
+                .is_some_and(|flags| flags[3] & 4 != 0);
+            // VBRI sits at a fixed 32 bytes past the frame header
+            let vbri = mp3_first_frame(head).and_then(|frame| frame.get(36..40)) == Some(b"VBRI");
+            Some(toc || vbri)
+        }
+        "mp4" => {
+            let boxes = top_level_chunks(source, 0, file_size, false);
+            let has = |id: &[u8; 4]| boxes.iter().any(|(b, _, _)| b == id);
+            // Fragmented files only get an index from a trailing mfra box
+            Some(has(b"moov") && (!has(b"moof") || has(b"mfra")))
+        }
+        "matroska" | "webm" => {
+            // The Cues element ID, found in the SeekHead or the Cues themselves
+            let cues = |bytes: &[u8]| bytes.windows(4).any(|w| w == b"\x1c\x53\xbb\x6b");
+            Some(cues(head) || cues(tail))
+        }
+        "ogg" | "adts" => Some(false),
+        _ => None,
+    }
+}
+
+/// The input sample rate recorded in an Opus identification header. Opus
+/// always decodes at 48 kHz; this is the rate of the source that was encoded.
+fn opus_input_rate(head: &[u8]) -> Option<u32> {
+    let at = head.windows(8).position(|w| w == b"OpusHead")?;
+    let rate = u32::from_le_bytes(head.get(at + 12..at + 16)?.try_into().ok()?);
+    // Zero means the encoder didn't say
+    (rate != 0).then_some(rate)
+}
+
+const FLAC_SEEKTABLE: u8 = 3;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+const FLAC_PICTURE: u8 = 6;
+
+/// The body of the first FLAC metadata block of type `kind`, cut short if it
+/// runs past the end of `head`.
+fn flac_block(head: &[u8], kind: u8) -> Option<&[u8]> {
+    let mut pos = 4;
+    while let Some(header) = head.get(pos..pos + 4) {
+        let block_len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        if header[0] & 0x7f == kind {
+            return head.get(pos + 4..(pos + 4 + block_len).min(head.len()));
+        }
+        // The last block has the top bit set
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+        pos += 4 + block_len;
+    }
+    None
+}
+
+/// The encoder vendor string from a FLAC, Ogg Vorbis or Opus comment header.
+fn vorbis_vendor(head: &[u8]) -> Option<String> {
+    if head.starts_with(b"fLaC") {
+        return read_le_string(flac_block(head, FLAC_VORBIS_COMMENT)?);
+    }
+
+    // Ogg: the comment packet follows its codec's magic
+    for magic in [&b"\x03vorbis"[..], b"OpusTags"] {
+        if let Some(at) = head.windows(magic.len()).position(|w| w == magic) {
+            return read_le_string(&head[at + magic.len()..]);
+        }
+    }
+    None
+}
+
+/// How much of the audio the header promises must be present before a file is
+/// not considered truncated.
+const TRUNCATION_RATIO: f64 = 0.95;
+
+/// CRC-8 (polynomial 0x07) as used by FLAC frame headers.
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Parse a FLAC frame header at the start of `bytes`, returning the number of
+/// the frame's first sample. `max_block` converts fixed-blocksize frame numbers.
+fn flac_frame_start(bytes: &[u8], max_block: u64) -> Option<u64> {
+    if bytes.len() < 6 || bytes[0] != 0xff || bytes[1] & 0xfe != 0xf8 {
+        return None;
+    }
+    let variable = bytes[1] & 1 != 0;
+    let block_code = bytes[2] >> 4;
+    let rate_code = bytes[2] & 0x0f;
+    if rate_code == 0x0f || bytes[3] >> 4 > 10 || bytes[3] & 0x01 != 0 {
+        return None;
+    }
+
+    // The frame/sample number is coded like UTF-8, up to 36 bits
+    let lead = bytes[4];
+    let extra = lead.leading_ones() as usize;
+    if extra == 1 || extra > 7 {
+        return None;
+    }
+    let mut number = u64::from(if extra == 0 {
+        lead
+    } else {
+        lead & (0x7f >> extra)
+    });
+    let mut pos = 5;
+    for _ in 1..extra {
+        let byte = *bytes.get(pos)?;
+        if byte & 0xc0 != 0x80 {
+            return None;
+        }
+        number = (number << 6) | u64::from(byte & 0x3f);
+        pos += 1;
+    }
+
+    pos += match block_code {
+        6 => 1,
+        7 => 2,
+        _ => 0,
+    };
+    pos += match rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(bytes.get(..pos)?) != *bytes.get(pos)? {
+        return None;
+    }
+
+    Some(if variable { number } else { number * max_block })
+}
+
+/// Whether the file holds noticeably less audio than its header promises,
+/// where that can be told without decoding: from the data size for PCM, and
+/// from the position of the last FLAC frame in `tail`.
+fn likely_truncated(
+    params: &CodecParameters,
+    head: &[u8],
+    tail: &[u8],
+    data_size: u64,
+) -> Option<bool> {
+    let n_frames = params.n_frames?;
+
+    if codec_name(params).starts_with("pcm") {
+        let bits = params.bits_per_coded_sample.or(params.bits_per_sample)?;
+        let channels = params.channels?.count() as u64;
+        let expected = n_frames * channels * u64::from(bits).div_ceil(8);
+        return Some((data_size as f64) < expected as f64 * TRUNCATION_RATIO);
+    }
+
+    if head.starts_with(b"fLaC") {
+        // STREAMINFO is always the first block; its max block size is bytes 2..4
+        let max_block = u64::from(u16::from_be_bytes(head.get(10..12)?.try_into().ok()?));
+        let last_start = (0..tail.len().saturating_sub(1))
+            .rev()
+            .find_map(|at| flac_frame_start(&tail[at..], max_block))?;
+        let present = last_start + max_block;
+        return Some((present as f64) < n_frames as f64 * TRUNCATION_RATIO);
+    }
+
+    None
+}
+
+/// Whether an error is an I/O failure that may succeed on retry, as opposed
+/// to a missing, truncated or unsupported file.
+fn is_transient(e: &AudioMetaError) -> bool {
+    let io_transient = |e: &io::Error| {
+        !matches!(
+            e.kind(),
+            io::ErrorKind::NotFound
+                | io::ErrorKind::PermissionDenied
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::InvalidData
+        )
+    };
+
+    match e {
+        AudioMetaError::Io(e)
+        | AudioMetaError::Probe(SymphoniaError::IoError(e))
+        | AudioMetaError::Decode(SymphoniaError::IoError(e)) => io_transient(e),
+        AudioMetaError::Http(e) => matches!(
+            e,
+            ureq::Error::Io(_) | ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed
+        ),
+        _ => false,
+    }
+}
+
+/// Analyze a file or URL, retrying transient I/O failures `options.retries` times.
+pub fn analyze_input(input: &Input, options: &Options) -> Result<AudioInfo, AudioMetaError> {
+    let mut attempt = 0;
+    loop {
+        let result = match input {
+            Input::File(path) => analyze_with(path, options),
+            Input::Url(url) => analyze_url(url, options),
+            Input::Archived(entry) => analyze_archived(entry, options),
+        };
+        match result {
+            Err(e) if attempt < options.retries && is_transient(&e) => {
+                thread::sleep(Duration::from_millis(100 << attempt.min(6)));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Read a file's header metadata and tags, without any of the decode passes.
+pub fn analyze(path: &Path) -> Result<AudioInfo, AudioMetaError> {
+    analyze_with(path, &Options::default())
+}
+
+/// Analyze a local file with the given options.
+pub fn analyze_with(path: &Path, options: &Options) -> Result<AudioInfo, AudioMetaError> {
+    let meta = fs::metadata(path)?;
+    let file_size = meta.len();
+    let reader = Box::new(File::open(path)?);
+    let ext = path.extension().and_then(|ext| ext.to_str());
+
+    // Hash the canonical path so the id doesn't depend on how the file was named
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let id = source_id(canonical.to_string_lossy().as_bytes());
+
+    let mut info = process_source(
+        reader,
+        file_size,
+        ext,
+        path.display().to_string(),
+        id,
+        options,
+    )?;
+    info.modified_time = meta.modified().ok().map(rfc3339);
+    info.created_time = meta.created().ok().map(rfc3339);
+    if path.to_str().is_none() {
+        info.warnings
+            .push("path is not valid UTF-8 and is shown lossily".to_string());
+    }
+    Ok(info)
+}
+
+/// A filesystem timestamp as RFC 3339 in UTC, to the second.
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Read a file out of a tar archive into memory and analyze it like a local one.
+pub fn analyze_archived(
+    entry: &ArchiveEntry,
+    options: &Options,
+) -> Result<AudioInfo, AudioMetaError> {
+    let body = entry.data.read(entry.offset, entry.size)?;
+
+    let file_path = format!("{}!{}", entry.archive.display(), entry.name);
+    let canonical = fs::canonicalize(&entry.archive).unwrap_or_else(|_| entry.archive.clone());
+    let id = source_id(format!("{}!{}", canonical.to_string_lossy(), entry.name).as_bytes());
+    let ext = Path::new(&entry.name)
+        .extension()
+        .and_then(|ext| ext.to_str());
+
+    process_source(
+        Box::new(io::Cursor::new(body)),
+        entry.size,
+        ext,
+        file_path,
+        id,
+        options,
+    )
+}
+
+/// Fetch a remote file into memory and analyze it like a local one.
+pub fn analyze_url(url: &str, options: &Options) -> Result<AudioInfo, AudioMetaError> {
+    let body = ureq::get(url)
+        .call()?
+        .into_body()
+        .with_config()
+        .limit(u64::MAX)
+        .read_to_vec()?;
+    let file_size = body.len() as u64;
+
+    let source = Box::new(io::Cursor::new(body));
+
+    let id = source_id(url.as_bytes());
+
+    process_source(
+        source,
+        file_size,
+        url_extension(url),
+        url.to_string(),
+        id,
+        options,
+    )
+}
+
+/// The first 16 hex digits of the BLAKE3 hash of a path or URL.
+fn source_id(name: &[u8]) -> String {
+    blake3::hash(name).to_hex()[..16].to_string()
+}
+
+fn process_source(
+    source: Box<dyn MediaSource>,
+    file_size: u64,
+    ext: Option<&str>,
+    file_path: String,
+    id: String,
+    options: &Options,
+) -> Result<AudioInfo, AudioMetaError> {
+    // Symphonia's probe error for this is unhelpful
+    if file_size == 0 {
+        return Err(AudioMetaError::Empty);
+    }
+
+    let mut hint = Hint::new();
+    // An explicit format overrides a missing or misleading extension
+    let ext = match options.input_format.as_deref() {
+        Some(format) if format.contains('/') => {
+            hint.mime_type(format);
+            None
+        }
+        Some(format) => Some(format),
+        None => ext,
+    };
+    if let Some(ext_str) = ext {
+        hint.with_extension(ext_str);
+    }
+
+    // Symphonia can't tell encrypted audio from any other track it has no
+    // codec for, so look at the sample entries before probing
+    let mut source = source;
+    let protection = mp4_protection(&mut source, file_size);
+    let file_size_bytes = file_size;
+    let mut file_size = file_size;
+    if let (Some(scheme), Some(helper)) = (protection, &options.decrypt_helper) {
+        source.seek(SeekFrom::Start(0))?;
+        let decrypted = decrypt_with_helper(helper, options.key.as_ref(), scheme, source)?;
+        file_size = decrypted.len() as u64;
+        source = Box::new(io::Cursor::new(decrypted));
+    }
+    source.seek(SeekFrom::Start(0))?;
+    let mss = MediaSourceStream::new(source, Default::default());
+
+    let mut probed = match (probe(mss, &hint, ext), protection) {
+        (Ok(probed), _) => probed,
+        (Err(_), Some(scheme)) => return Err(AudioMetaError::Protected(scheme)),
+        (Err(e), None) => return Err(e),
+    };
+    let mut format = probed.format;
+
+    // Containers like MP4 also list video/text tracks, which have no audio codec
+    let audio_tracks: Vec<_> = format
+        .tracks()
+        .iter()
+        .filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .collect();
+    let track_count = audio_tracks.len();
+    let track = match options.track {
+        Some(n) => *audio_tracks
+            .get(n.wrapping_sub(1))
+            .ok_or(AudioMetaError::NoSuchTrack(n, track_count))?,
+        None => audio_tracks
+            .first()
+            .copied()
+            // What's left of protected audio is a track without one
+            .or_else(|| protection.and(format.tracks().first()))
+            .ok_or(AudioMetaError::NoTrack)?,
+    };
+    let track_id = track.id;
+    let mut params = track.codec_params.clone();
+    // Matroska only gives a layout; decoders and measurements want the channels
+    if params.channels.is_none()
+        && let Some(layout) = params.channel_layout
+    {
+        params.with_channels(layout.into_channels());
+    }
+
+    let mut info = AudioInfo {
+        file_path,
+        id,
+        sample_rate: params.sample_rate,
+        original_sample_rate: None,
+        channels: params.channels.map(|ch| ch.count() as u8),
+        bits_per_sample: params.bits_per_sample,
+        duration_seconds: None,
+        avg_bitrate_kbps: None,
+        tags: Vec::new(),
+        file_size_bytes,
+        modified_time: None,
+        created_time: None,
+        codec: Some(codec_name(&params)),
+        codec_profile: None,
+        container: None,
+        drm: None,
+        integrated_lufs: None,
+        loudness_range_lu: None,
+        sample_peak: None,
+        channel_peaks: Vec::new(),
+        audio_data_offset: None,
+        metadata_size_bytes: None,
+        stream_count: format.tracks().len(),
+        track_count,
+        track_index: options.track.unwrap_or(1),
+        vendor: None,
+        frequency_cutoff_hz: None,
+        likely_resampled: None,
+        flac_audio_md5: match params.verification_check {
+            Some(VerificationCheck::Md5(md5)) => {
+                Some(md5.iter().map(|b| format!("{b:02x}")).collect())
+            }
+            _ => None,
+        },
+        flac_md5_ok: None,
+        likely_truncated: None,
+        lame_preset: None,
+        lame_vbr_quality: None,
+        has_lyrics: false,
+        lyrics: None,
+        synced_lyrics: None,
+        isrc: None,
+        barcode: None,
+        musicbrainz_trackid: None,
+        musicbrainz_albumid: None,
+        bpm: None,
+        bpm_estimated: None,
+        content_type: None,
+        compression_ratio: None,
+        declared_duration_seconds: None,
+        decoded_duration_seconds: None,
+        decode_errors: None,
+        sample_format: None,
+        channel_order: Vec::new(),
+        max_frames_per_packet: None,
+        seekable: None,
+        seek_accuracy: None,
+        has_seek_index: None,
+        metadata_blocks: Vec::new(),
+        chunks: Vec::new(),
+        metadata_at_end: None,
+        tag_count: 0,
+        tag_bytes: 0,
+        art: Vec::new(),
+        warnings: Vec::new(),
+        explain: Vec::new(),
+    };
+
+    // Calculate duration and bitrate
+    if let (Some(time_base), Some(n_frames)) = (params.time_base, params.n_frames) {
+        let duration = time_base.calc_time(n_frames);
+        let seconds = duration.seconds as f64 + duration.frac;
+        info.duration_seconds = Some(duration.seconds);
+        info.avg_bitrate_kbps = avg_bitrate_kbps(file_size, seconds);
+        if options.verify_duration {
+            info.declared_duration_seconds = Some(seconds);
+        }
+        info.explain(options.explain, "duration_seconds", || {
+            format!("n_frames * time_base from the container header ({n_frames} * {time_base} = {seconds:.3}s)")
+        });
+        info.explain(options.explain, "avg_bitrate_kbps", || {
+            format!("file_size_bytes * 8 / duration, whole file ({file_size} * 8 / {seconds:.3}s)")
+        });
+    } else {
+        info.warnings
+            .push("duration unknown: the header gives no frame count".to_string());
+        info.explain(options.explain, "duration_seconds", || {
+            "unknown: the container header gives no frame count".to_string()
+        });
+    }
+    if let Some((ratio, how)) = compression_ratio(&params, file_size) {
+        info.compression_ratio = Some(ratio);
+        info.explain(options.explain, "compression_ratio", || how);
+    }
+
+    // Collect tags: ID3v2 is read by the probe ahead of the container, the
+    // rest by the format reader
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut collect = |rev: &MetadataRevision| {
+        tags.extend(rev.tags().iter().cloned());
+        info.art.extend(
+            rev.visuals()
+                .iter()
+                .map(|visual| Artwork::new(visual, options.art_data)),
+        );
+    };
+    if let Some(rev) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        collect(rev);
+    }
+    if let Some(rev) = format.metadata().current() {
+        collect(rev);
+    }
+
+    info.tag_count = tags.len();
+
+    // Identifiers go by many raw spellings, so they get fields of their own
+    if let Some(isrc) = identifier(&tags, &[StandardTagKey::IdentIsrc], &["isrc", "tsrc"]) {
+        match normalize_isrc(&isrc) {
+            Some(isrc) => info.isrc = Some(isrc),
+            None => info
+                .warnings
+                .push(format!("ISRC tag '{isrc}' is not a valid ISRC")),
+        }
+    }
+    info.barcode = identifier(
+        &tags,
+        &[
+            StandardTagKey::IdentBarcode,
+            StandardTagKey::IdentUpc,
+            StandardTagKey::IdentEanUpn,
+        ],
+        &["barcode", "upc", "ean"],
+    );
+    info.musicbrainz_trackid = identifier(
+        &tags,
+        &[StandardTagKey::MusicBrainzTrackId],
+        &["musicbrainztrackid"],
+    );
+    info.musicbrainz_albumid = identifier(
+        &tags,
+        &[StandardTagKey::MusicBrainzAlbumId],
+        &["musicbrainzalbumid"],
+    );
+    if let Some(bpm) = identifier(&tags, &[StandardTagKey::Bpm], &["bpm", "tbpm", "tmpo"]) {
+        match bpm.replace(',', ".").parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => {
+                info.bpm = Some(value);
+                info.bpm_estimated = Some(false);
+                info.explain(options.explain, "bpm", || {
+                    format!("from the BPM tag ('{bpm}')")
+                });
+            }
+            _ => info
+                .warnings
+                .push(format!("BPM tag '{bpm}' is not a number")),
+        }
+    }
+
+    info.lyrics = tags
+        .iter()
+        .find(|tag| tag.std_key == Some(StandardTagKey::Lyrics))
+        .map(|tag| tag_text(&tag.value))
+        .filter(|text| !text.trim().is_empty());
+    info.tags = Vec::with_capacity(tags.len());
+    for tag in &tags {
+        // Cover art and the like would only come out as a wall of hex or base64
+        let size = match &tag.value {
+            Value::Binary(data) => Some(data.len()),
+            Value::String(text) if is_binary_text(&tag.key, text) => Some(text.len()),
+            _ => None,
+        };
+        match size {
+            Some(size) if !options.binary_tags => {
+                info.warnings.push(format!(
+                    "skipped binary tag {} ({size} bytes)",
+                    normalize_key(tag)
+                ));
+            }
+            _ => info.tags.push((normalize_key(tag), tag_text(&tag.value))),
+        }
+    }
+
+    if let Some(form) = options.unicode_form {
+        // Before deduping, so keys differing only in form count as the same
+        for (key, value) in &mut info.tags {
+            *key = form.apply(key);
+            *value = form.apply(value);
+        }
+    }
+    dedupe_tags(&mut info.tags, options.duplicate_tags);
+
+    if options.sort_tags {
+        // Stable, so duplicate keys keep their relative file order
+        info.tags.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    // The reader stops at the first audio packet, past any leading tags/headers
+    let mut source = format.into_inner();
+    let offset = source.pos();
+    info.audio_data_offset = Some(offset);
+
+    // Some header details symphonia parses but doesn't keep, so read them directly
+    source.seek(SeekFrom::Start(0))?;
+    let mut head = Vec::new();
+    (&mut source).take(HEAD_BYTES).read_to_end(&mut head)?;
+    info.container = container_name(&head).map(str::to_string);
+    if info.container.as_deref() == Some("mp4") {
+        info.drm = Some(protection.is_some());
+    }
+    info.vendor = vorbis_vendor(&head);
+    info.codec_profile = aac_profile(&params, &head);
+    if info.codec_profile.is_some() {
+        let source = match params.extra_data {
+            Some(_) => "the AudioSpecificConfig in the MP4/Matroska codec data",
+            None => "the first ADTS frame header",
+        };
+        info.explain(options.explain, "codec_profile", || {
+            format!("AAC object type from {source}")
+        });
+    }
+    if params.codec == codecs::CODEC_TYPE_OPUS {
+        info.original_sample_rate =
+            opus_input_rate(&head).filter(|&rate| Some(rate) != info.sample_rate);
+        if info.original_sample_rate.is_some() {
+            info.explain(options.explain, "original_sample_rate", || {
+                "input sample rate from the OpusHead header".to_string()
+            });
+        }
+    }
+    if let Some((preset, vbr_quality)) = lame_tag(&head) {
+        info.lame_preset = preset;
+        info.lame_vbr_quality = vbr_quality;
+    }
+    // Symphonia skips SYLT frames, so walk the ID3v2 tag for them, reading the
+    // whole tag when it runs past the head (large cover art)
+    if let Some(len) = id3v2_len(&head) {
+        if len > head.len() {
+            let mut tag = Vec::new();
+            source.seek(SeekFrom::Start(0))?;
+            (&mut source).take(len as u64).read_to_end(&mut tag)?;
+            info.synced_lyrics = synced_lyrics(&tag);
+        } else {
+            info.synced_lyrics = synced_lyrics(&head);
+        }
+    }
+    info.has_lyrics = info.lyrics.is_some() || info.synced_lyrics.is_some();
+    info.metadata_blocks = metadata_blocks(&mut source, &head, file_size)?;
+    info.chunks = riff_chunks(&mut source, &head, file_size);
+    if !info.metadata_blocks.is_empty() {
+        info.metadata_at_end = Some(info.metadata_blocks.iter().any(|block| block.at_end));
+    }
+    // Unknown if a trailing block's size is
+    let trailing: Option<u64> = info
+        .metadata_blocks
+        .iter()
+        .filter(|block| block.at_end)
+        .map(|block| block.size_bytes)
+        .sum();
+    info.metadata_size_bytes = trailing
+        .map(|trailing| offset + trailing)
+        .filter(|&size| size <= file_size);
+    info.tag_bytes = match info
+        .metadata_blocks
+        .iter()
+        .map(|block| block.size_bytes)
+        .sum()
+    {
+        Some(total) if !info.metadata_blocks.is_empty() => total,
+        // Without every block's size, estimate from what the tags hold
+        _ => tag_value_bytes(&tags, &info.art),
+    };
+    let mut tail = Vec::new();
+    if matches!(
+        info.container.as_deref(),
+        Some("flac" | "matroska" | "webm")
+    ) {
+        source.seek(SeekFrom::Start(file_size.saturating_sub(HEAD_BYTES)))?;
+        source.read_to_end(&mut tail)?;
+    }
+    info.likely_truncated =
+        likely_truncated(&params, &head, &tail, file_size.saturating_sub(offset));
+    if info.likely_truncated == Some(true) {
+        info.warnings
+            .push("the file holds less audio than its header promises".to_string());
+    }
+    info.has_seek_index = has_seek_index(&mut source, &head, &tail, file_size);
+
+    // The tags are readable, but nothing past them is
+    if let Some(scheme) = protection
+        && params.codec == CODEC_TYPE_NULL
+    {
+        info.codec = None;
+        info.warnings
+            .push(format!("DRM-protected ({scheme}), cannot read audio"));
+        return Ok(info);
+    }
+
+    // Each pass below gets a fresh format reader, since the one before moved it
+    let reprobe = |mut source: MediaSourceStream| -> Result<Box<dyn FormatReader>, AudioMetaError> {
+        source.seek(SeekFrom::Start(0))?;
+        Ok(probe(source, &hint, ext)?.format)
+    };
+
+    // Seeking is up to the format reader, and costs a second probe to find out
+    if options.seek_test {
+        let mut format = reprobe(source)?;
+        let accuracy = seek_accuracy(&mut format, track_id, &params);
+        info.seekable = accuracy.map(|accuracy| accuracy != "none");
+        info.seek_accuracy = accuracy.map(str::to_string);
+        source = format.into_inner();
+    }
+
+    if options.decode_info {
+        let mut format = reprobe(source)?;
+        decoder_output(&mut format, track_id, &params, &mut info)?;
+        source = format.into_inner();
+    }
+
+    if options.loudness
+        || options.peaks
+        || options.spectral
+        || options.resample_check
+        || options.verify_md5
+        || options.verify_duration
+        || options.check
+        || (options.detect_bpm && info.bpm.is_none())
+    {
+        let mut format = reprobe(source)?;
+        analyze_audio(&mut format, track_id, &params, options, &mut info)?;
+        source = format.into_inner();
+    }
+
+    if options.classify {
+        let mut format = reprobe(source)?;
+        classify_audio(&mut format, track_id, &params, options, &mut info)?;
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_byte_file_is_empty() {
+        let file = tempfile::Builder::new().suffix(".mp3").tempfile().unwrap();
+        let err = analyze(file.path()).unwrap_err();
+        assert!(matches!(err, AudioMetaError::Empty));
+        assert_eq!(err.to_string(), "empty file");
+    }
+
+    #[test]
+    fn avg_bitrate_rejects_bad_durations() {
+        assert_eq!(avg_bitrate_kbps(1_000, 0.0), None);
+        assert_eq!(avg_bitrate_kbps(1_000, -1.0), None);
+        assert_eq!(avg_bitrate_kbps(1_000, f64::NAN), None);
+        assert_eq!(avg_bitrate_kbps(1_000, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn avg_bitrate_at_boundary_sizes() {
+        assert_eq!(avg_bitrate_kbps(0, 1.0), Some(0));
+        assert_eq!(avg_bitrate_kbps(16_000, 1.0), Some(128));
+        // 4 TB over 1000 s
+        assert_eq!(
+            avg_bitrate_kbps(4_000_000_000_000, 1_000.0),
+            Some(32_000_000)
+        );
+        // Too large to be real, rather than saturated to u32::MAX
+        assert_eq!(avg_bitrate_kbps(u64::MAX, 1.0), None);
+    }
+
+    #[test]
+    fn stdin_directory_is_walked_when_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        for name in [
+            "top.mp3",
+            "a/mid.flac",
+            "a/b/deep.MP3",
+            "a/cover.jpg",
+            "a/b/notes.txt",
+        ] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+        let exts = vec!["mp3".to_string(), "flac".to_string()];
+        let walk = Walk {
+            recursive: true,
+            sorted: true,
+            order: WalkOrder::DepthFirst,
+        };
+        let lines = format!("{}\n\n", dir.path().display());
+
+        let inputs = collect_from_lines(lines.as_bytes(), &exts, walk, |e| panic!("{e}")).unwrap();
+        let mut found: Vec<_> = inputs
+            .iter()
+            .map(|input| match input {
+                Input::File(path) => path.strip_prefix(dir.path()).unwrap().to_path_buf(),
+                _ => panic!("not a local file: {input}"),
+            })
+            .collect();
+        found.sort();
+        let expected: Vec<PathBuf> = ["a/b/deep.MP3", "a/mid.flac", "top.mp3"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn stdin_directory_needs_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = format!("{}\n", dir.path().display());
+        let mut warnings = Vec::new();
+
+        let inputs =
+            collect_from_lines(lines.as_bytes(), &[], Walk::default(), |e| warnings.push(e))
+                .unwrap();
+        assert!(inputs.is_empty());
+        assert!(matches!(
+            warnings.as_slice(),
+            [AudioMetaError::IsDirectory(_)]
+        ));
+    }
+
+    #[test]
+    fn tag_text_escapes_control_characters() {
+        let value = Value::from("\x1b[31mred\0mid\r\nnext\0\0");
+        // Line breaks stay, trailing NUL padding goes, the rest is escaped
+        assert_eq!(tag_text(&value), "\\u{1b}[31mred\\u{0}mid\r\nnext");
+    }
+
+    /// An EBML header declaring `doc_type`, followed by the start of a Segment.
+    fn ebml_header(doc_type: &[u8]) -> Vec<u8> {
+        let mut body = vec![0x42, 0x86, 0x81, 0x01]; // EBMLVersion 1
+        body.extend_from_slice(&[0x42, 0x82, 0x80 | doc_type.len() as u8]);
+        body.extend_from_slice(doc_type);
+        let mut head = vec![0x1a, 0x45, 0xdf, 0xa3, 0x80 | body.len() as u8];
+        head.extend(body);
+        head.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0x01, 0xff]);
+        head
+    }
+
+    #[test]
+    fn container_name_reads_ebml_doc_type() {
+        assert_eq!(container_name(&ebml_header(b"webm")), Some("webm"));
+        assert_eq!(container_name(&ebml_header(b"matroska")), Some("matroska"));
+
+        // "webm" after the EBML header, in a title say, doesn't make it WebM
+        let mut head = ebml_header(b"matroska");
+        head.extend_from_slice(b"webm");
+        assert_eq!(container_name(&head), Some("matroska"));
+    }
+
+    #[test]
+    fn container_name_survives_bad_ebml_sizes() {
+        // A zero length byte, and the largest 8-byte size
+        let mut zero = vec![0x1a, 0x45, 0xdf, 0xa3, 0x00];
+        zero.extend_from_slice(&[0xff; 8]);
+        assert_eq!(container_name(&zero), Some("matroska"));
+        let mut huge = vec![0x1a, 0x45, 0xdf, 0xa3, 0x01];
+        huge.extend_from_slice(&[0xff; 7]);
+        huge.extend_from_slice(b"webm");
+        assert_eq!(container_name(&huge), Some("webm"));
+    }
+}
@@ -1,328 +1,4532 @@
-use clap::{Parser, ValueEnum};
+// info_json's json! literal has outgrown the default macro recursion limit
+#![recursion_limit = "256"]
+
+use audiometa::{
+    AudioInfo, AudioMetaError, DuplicateTags, Input, Key, Options, UnicodeForm, Walk, WalkOrder,
+    collect_audio_files, collect_from_stdin, inputs_from_line, is_archive, is_audio_file,
+    url_extension,
+};
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use convert_case::{Case, Casing};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
     fs::{self, File},
-    io::{self, BufRead, IsTerminal},
-    path::PathBuf,
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write},
+    ops::Range,
+    path::{Component, Path, PathBuf},
     process,
-};
-use symphonia::core::{
-    formats::FormatOptions,
-    io::MediaSourceStream,
-    meta::{MetadataOptions, Tag},
-    probe::Hint,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Clone, ValueEnum)]
 enum OutputFormat {
+    /// Infer from the `--output` extension, falling back to text
+    Auto,
     Text,
     Json,
+    /// One JSON object per line (newline-delimited JSON), which --append can add to
+    #[value(alias = "jsonl")]
+    Ndjson,
     Csv,
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// A Markdown (GitHub-flavored) table
+    #[value(alias = "markdown")]
+    Md,
+    /// A self-contained HTML page with a table that sorts by any column
+    Html,
+    /// An Apache Parquet file, written to `--output` (needs the `parquet`
+    /// feature)
+    Parquet,
+}
+
+/// How tag keys are written in the output; matching always uses snake_case.
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyCase {
+    /// `track_title`
+    Snake,
+    /// `TRACK_TITLE`
+    Upper,
+    /// `track-title`
+    Kebab,
+    /// `trackTitle`
+    Camel,
+    /// `TrackTitle`
+    Pascal,
+    /// `Track Title`
+    Title,
+}
+
+impl KeyCase {
+    fn apply(self, key: &str) -> String {
+        match self {
+            KeyCase::Snake => key.to_string(),
+            KeyCase::Upper => key.to_case(Case::UpperSnake),
+            KeyCase::Kebab => key.to_case(Case::Kebab),
+            KeyCase::Camel => key.to_case(Case::Camel),
+            KeyCase::Pascal => key.to_case(Case::Pascal),
+            KeyCase::Title => key.to_case(Case::Title),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OnError {
+    /// Stop at the first file that fails
+    Abort,
+    /// Report each failure and carry on (as --keep-going)
+    Continue,
+    /// Carry on, reporting only the first file of each unsupported codec or format
+    #[value(alias = "skip-format")]
+    SkipCodec,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Traversal {
+    /// Depth-first: each subdirectory in full before the next entry
+    Dfs,
+    /// Breadth-first: every file at one depth before any deeper one
+    Bfs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DedupeTags {
+    /// Keep the first value
+    First,
+    /// Keep the last value
+    Last,
+    /// Join the distinct values with "; "
+    Join,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NormalizeUnicode {
+    /// Composed characters (NFC)
+    Nfc,
+    /// Decomposed characters (NFD)
+    Nfd,
 }
 
 #[derive(Parser)]
-#[command(author, version, about = "Show audio file technical metadata")]
+#[command(
+    author,
+    version,
+    about = "Show audio file technical metadata",
+    args_conflicts_with_subcommands = true
+)]
+struct Invocation {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Without a subcommand, the same as `show`
+    #[command(flatten)]
+    cli: Cli,
+}
+
+#[derive(Args)]
 struct Cli {
     /// One or more audio files (omit to read file paths from stdin)
     files: Vec<PathBuf>,
 
     /// Output format
-    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    #[arg(short, long, value_enum, env = "AUDIOMETA_FORMAT", default_value_t = OutputFormat::Auto)]
     format: OutputFormat,
 
+    /// Write output to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Add to the --output file instead of replacing it, leaving out the CSV
+    /// header when the file already has content. For CSV, NDJSON and text;
+    /// CSV must have the same columns as the file's header
+    #[arg(long, requires = "output", conflicts_with = "json_stream")]
+    append: bool,
+
+    /// Read default options from this TOML file instead of ./.audiometa.toml
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
     /// Show only basic info (duration, bitrate, sample rate)
     #[arg(short, long)]
     basic: bool,
 
+    /// Include more detail in text output, such as a WAV file's chunk list
+    #[arg(short, long, conflicts_with = "basic")]
+    verbose: bool,
+
     /// Suppress error messages
     #[arg(short, long)]
     quiet: bool,
 
+    /// Print nothing at all when every file succeeds; if any fails, print the
+    /// usual output and messages at the end of the run
+    #[arg(long, conflicts_with_all = ["json_stream", "follow_file_list"])]
+    quiet_on_success: bool,
+
+    /// Write error and warning messages to this file instead of stderr
+    #[arg(long, value_name = "FILE")]
+    errors_to: Option<PathBuf>,
+
+    /// Keep results in this file and reuse them on later runs for files whose
+    /// size and modification time haven't changed
+    #[arg(
+        long,
+        value_name = "FILE",
+        alias = "since-last-run",
+        conflicts_with = "follow_file_list"
+    )]
+    cache: Option<PathBuf>,
+
     /// Continue processing other files even if one fails
     #[arg(short = 'k', long)]
     keep_going: bool,
 
+    /// What to do when a file fails (default: abort, or continue with --keep-going)
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "keep_going")]
+    on_error: Option<OnError>,
+
+    /// Exit non-zero when inputs were skipped with a warning, such as
+    /// directories a --recursive walk couldn't read
+    #[arg(long)]
+    strict: bool,
+
     /// Recursive directory processing
     #[arg(short, long)]
     recursive: bool,
-}
 
-#[derive(Debug)]
-struct AudioInfo {
-    file_path: String,
-    sample_rate: Option<u32>,
-    channels: Option<u8>,
-    duration_seconds: Option<u64>,
-    avg_bitrate_kbps: Option<u32>,
-    tags: Vec<(String, String)>,
-    file_size_bytes: u64,
-    codec: Option<String>,
-}
+    /// Walk directories in filesystem order instead of sorting by path
+    #[arg(long)]
+    no_sort: bool,
+
+    /// Order to walk directories in with --recursive
+    #[arg(long, value_enum, value_name = "ORDER", default_value = "dfs")]
+    walk_order: Traversal,
+
+    /// Process at most this many of the files found
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Process this many files picked at random from those found
+    #[arg(long, value_name = "N", conflicts_with_all = ["limit", "follow_file_list"])]
+    sample: Option<usize>,
+
+    /// Seed for --sample, to pick the same files again (default: random)
+    #[arg(long, value_name = "N", requires = "sample")]
+    seed: Option<u64>,
+
+    /// Comma-separated file extensions treated as audio
+    #[arg(
+        long,
+        value_name = "LIST",
+        value_delimiter = ',',
+        env = "AUDIOMETA_EXT",
+        default_value = "mp3,flac,ogg,wav,aac,m4a,m4b,m4p,aax,wma,tak,mpc,mka,weba"
+    )]
+    ext: Vec<String>,
+
+    /// Comma-separated extensions to accept in addition to --ext
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    add_ext: Vec<String>,
+
+    /// Comma-separated extensions to skip, applied after --ext and --add-ext
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    exclude_ext: Vec<String>,
+
+    /// Probe every input as this format, an extension such as flac or a MIME
+    /// type such as audio/flac, instead of going by its extension. Files named
+    /// on the command line are then taken whatever their extension
+    #[arg(long, value_name = "FMT")]
+    input_format: Option<String>,
+
+    /// Program that decrypts DRM-protected files: it gets the file on stdin,
+    /// the --key in hex in AUDIOMETA_KEY and the scheme in AUDIOMETA_DRM, and
+    /// writes the decrypted file to stdout. Without one, protected files
+    /// report drm and their tags only
+    #[arg(long, value_name = "PROGRAM")]
+    decrypt_helper: Option<PathBuf>,
+
+    /// Key in hex for the --decrypt-helper
+    #[arg(
+        long,
+        value_name = "HEX",
+        value_parser = parse_key,
+        conflicts_with = "keyfile",
+        requires = "decrypt_helper"
+    )]
+    key: Option<Key>,
+
+    /// Read the --key from a file, keeping it out of the shell history
+    #[arg(long, value_name = "PATH", value_parser = parse_keyfile, requires = "decrypt_helper")]
+    keyfile: Option<Key>,
+
+    /// Report this audio track of multi-track files (MKA, MP4, ...), counting
+    /// from 1, instead of the first
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    track: Option<usize>,
+
+    /// Number of files to process in parallel; 1 processes them one at a time.
+    /// Defaults to the number of logical CPUs, at most 8
+    #[arg(
+        short,
+        long,
+        env = "AUDIOMETA_JOBS",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    jobs: Option<usize>,
+
+    /// Retry a file this many times on transient I/O errors
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+
+    /// Decode audio and compute EBU R128 integrated loudness (slow)
+    #[arg(long)]
+    loudness: bool,
+
+    /// Decode audio and report overall and per-channel sample peaks (slow)
+    #[arg(long)]
+    peaks: bool,
+
+    /// Decode audio and estimate the frequency cutoff, to spot lossy sources (slow)
+    #[arg(long)]
+    spectral: bool,
+
+    /// Decode audio and look for the steep low-pass a sample rate conversion
+    /// leaves under another rate's Nyquist frequency, e.g. 44.1 kHz audio
+    /// upsampled to 48 kHz (slow, heuristic)
+    #[arg(long)]
+    resample_check: bool,
+
+    /// Decode audio and estimate the tempo of files without a BPM tag (slow)
+    #[arg(long)]
+    detect_bpm: bool,
+
+    /// Decode about 20 seconds of audio and guess whether it is speech or music (heuristic)
+    #[arg(long)]
+    classify: bool,
+
+    /// Decode FLAC audio and check it against the MD5 stored in its header (slow)
+    #[arg(long)]
+    verify_md5: bool,
+
+    /// Decode the whole file and report the declared and decoded durations,
+    /// trusting the decoded one (slow)
+    #[arg(long)]
+    verify_duration: bool,
+
+    /// Decode the whole file and count the corrupt packets skipped along the
+    /// way (slow)
+    #[arg(long)]
+    check: bool,
+
+    /// Decode the first packet and report the decoder's sample format and
+    /// channel order
+    #[arg(long)]
+    decode_info: bool,
+
+    /// Open the file a second time and try seeking to the middle, to report
+    /// seekable and seek_accuracy
+    #[arg(long)]
+    seek_test: bool,
+
+    /// Show how derived fields (duration, bitrate, ...) were computed, as an
+    /// `_explain` object in JSON or `explain` lines in text
+    #[arg(long)]
+    explain: bool,
+
+    /// Report file paths as absolute paths
+    #[arg(long)]
+    absolute: bool,
+
+    /// Report file paths relative to this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "absolute")]
+    relative_to: Option<PathBuf>,
+
+    /// Report only the file name, without its directory
+    #[arg(long, alias = "strip-path", conflicts_with_all = ["absolute", "relative_to"])]
+    basename: bool,
+
+    /// Sort each file's tags by key instead of keeping file order
+    #[arg(long)]
+    sort_tags: bool,
+
+    /// Collapse tags whose keys normalize to the same name (default: keep all)
+    #[arg(long, value_enum, value_name = "MODE")]
+    dedupe_tags: Option<DedupeTags>,
+
+    /// Normalize tag keys and values to this Unicode form (default: leave them
+    /// as written)
+    #[arg(long, value_enum, value_name = "FORM")]
+    normalize_unicode: Option<NormalizeUnicode>,
 
-fn normalize_key(tag: &Tag) -> String {
-    tag.std_key
-        .map(|k| format!("{k:?}"))
-        .unwrap_or_else(|| tag.key.clone())
-        .to_case(Case::Snake)
+    /// Only list the files that failed to parse, with their errors (implies --keep-going)
+    #[arg(long, conflicts_with_all = ["diff", "json_stream", "suggest_rename", "output_dir"])]
+    unknown_only: bool,
+
+    /// Compare the metadata of exactly two files
+    #[arg(long)]
+    diff: bool,
+
+    /// With --diff, hide fields that match
+    #[arg(long, requires = "diff")]
+    diff_only: bool,
+
+    /// Write CSV with a column for every tag key seen across all files
+    #[arg(long)]
+    merge_csv: bool,
+
+    /// Stream a JSON array, writing each file's object as soon as it is done
+    #[arg(long, conflicts_with_all = ["diff", "merge_csv"])]
+    json_stream: bool,
+
+    /// With --json-stream or --follow-file-list, flush the output every N
+    /// records rather than after each one, for faster very large runs
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    jsonl_buffer: usize,
+
+    /// Comma-separated fields to show as table and CSV columns
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// List the fields --fields can select, with their types, and exit
+    #[arg(long)]
+    list_fields: bool,
+
+    /// Comma-separated tag keys (as shown in the output) to add as table and CSV columns
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    tag: Vec<String>,
+
+    /// Comma-separated tag keys to leave out of every report
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    exclude_tag: Vec<String>,
+
+    /// Rename a tag key in every report, e.g. album_artist=albumArtist
+    /// (repeatable); tags renamed to the same key are merged into one
+    #[arg(long, value_name = "OLD=NEW", value_parser = parse_rename)]
+    tag_rename: Vec<(String, String)>,
+
+    /// Keep binary tags and pictures stored as text (like Vorbis COVERART) in
+    /// the tags, hex-encoded or as stored, instead of skipping them with a warning
+    #[arg(long)]
+    include_binary_tags: bool,
+
+    /// Cut tag values longer than N characters down to N, ending in …, in
+    /// every output format
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    tag_value_max_len: Option<usize>,
+
+    /// Print only the tags, as key=value lines (limited to --tag keys when given)
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "diff", "json_stream", "suggest_rename", "output_dir", "unknown_only", "dump_lyrics",
+            "follow_file_list"
+        ]
+    )]
+    tags_only: bool,
+
+    /// Instead of a report per file, check each folder for tracks that differ
+    /// in sample rate, bit depth or codec
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "diff", "json_stream", "suggest_rename", "output_dir", "unknown_only", "dump_lyrics",
+            "tags_only", "follow_file_list", "group_by"
+        ]
+    )]
+    album_check: bool,
+
+    /// Instead of a report per file, print how many files have each value of
+    /// this field or tag, most common first
+    #[arg(
+        long,
+        value_name = "FIELD",
+        conflicts_with_all = [
+            "diff", "json_stream", "suggest_rename", "output_dir", "unknown_only", "dump_lyrics",
+            "tags_only", "follow_file_list", "group_by", "album_check"
+        ]
+    )]
+    count_by: Option<String>,
+
+    /// Instead of a report per file, print how many files fall into each
+    /// duration bin, as a bar chart in text
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "diff", "json_stream", "suggest_rename", "output_dir", "unknown_only", "dump_lyrics",
+            "tags_only", "follow_file_list", "group_by", "album_check", "count_by"
+        ]
+    )]
+    duration_histogram: bool,
+
+    /// Width of each --duration-histogram bin, in seconds or with an m or h
+    /// suffix (90, 30s, 5m)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "60",
+        value_parser = parse_bin_size,
+        requires = "duration_histogram"
+    )]
+    bin_size: u64,
+
+    /// Save embedded pictures into this directory as <file stem>.<type>.<ext>
+    #[arg(long, value_name = "DIR")]
+    extract_art: Option<PathBuf>,
+
+    /// With --extract-art, only save pictures of this type (e.g. front, back,
+    /// artist, or any type name shown in the art field)
+    #[arg(long, value_name = "TYPE", requires = "extract_art")]
+    art_type: Option<String>,
+
+    /// In JSON output, embed the front cover (or else the first picture) as a
+    /// data URI in an art_data_uri field; in HTML, show it as a thumbnail
+    #[arg(long)]
+    embed_art: bool,
+
+    /// With --embed-art, downscale pictures larger than N pixels on either side
+    #[arg(long, value_name = "N", requires = "embed_art", value_parser = clap::value_parser!(u32).range(1..))]
+    art_max_dim: Option<u32>,
+
+    /// After the run, print file count, bytes read, wall time and throughput to stderr
+    #[arg(long)]
+    profile: bool,
+
+    /// Truncate table cells to this many characters
+    #[arg(long, value_name = "N")]
+    max_width: Option<usize>,
+
+    /// Separator between keys and values in text output
+    #[arg(
+        long,
+        value_name = "STR",
+        default_value = ": ",
+        alias = "field-separator"
+    )]
+    text_sep: String,
+
+    /// Wrap tag values in text output to this many columns, with continuation
+    /// lines indented under the value; `auto` uses the terminal width
+    #[arg(long, value_name = "N|auto", value_parser = parse_wrap_width)]
+    wrap_width: Option<usize>,
+
+    /// Comma-separated codecs to keep (e.g. mp3,aac); others are dropped from the output
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    codec_only: Vec<String>,
+
+    /// Skip files larger than this size (e.g. 500M; K, M, G and T are powers of 1024)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Skip files smaller than this size (e.g. 4K)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_file_size: Option<u64>,
+
+    /// Keep only files with a tag value containing this text (case-insensitive);
+    /// use KEY=TEXT to search one tag
+    #[arg(long, value_name = "[KEY=]TEXT")]
+    tag_contains: Option<String>,
+
+    /// Keep only files with a tag value matching this regex
+    #[arg(long, value_name = "REGEX")]
+    tag_matches: Option<String>,
+
+    /// Keep only files without this tag; repeat to keep files missing any of
+    /// several
+    #[arg(long, value_name = "KEY")]
+    only_missing: Vec<String>,
+
+    /// How to write tag keys in the output
+    #[arg(long, value_enum, default_value_t = KeyCase::Snake)]
+    key_case: KeyCase,
+
+    /// Shorthand for --key-case upper
+    #[arg(long, conflicts_with = "key_case")]
+    uppercase_keys: bool,
+
+    /// Print a new file name for each file built from a template such as
+    /// "{track_number:02} - {track_title}"
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["diff", "json_stream"])]
+    suggest_rename: Option<String>,
+
+    /// With --suggest-rename, actually rename the files
+    #[arg(long, requires = "suggest_rename")]
+    rename: bool,
+
+    /// Write one report per input into this directory, named after the input
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["output", "json_stream", "diff", "suggest_rename"]
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// In JSON output, lift tags to top-level "tag.<key>" entries
+    #[arg(long, alias = "flatten-json")]
+    flatten: bool,
+
+    /// In JSON output, write integer fields as strings so large values survive
+    /// parsers that read numbers as doubles
+    #[arg(long)]
+    json_numbers_as_strings: bool,
+
+    /// In JSON output, sort object keys (fields and tags alike) so output from
+    /// different runs diffs cleanly
+    #[arg(long)]
+    json_sort_keys: bool,
+
+    /// Follow a growing list of paths (like `tail -f`), writing one JSON line
+    /// per file as each is appended, until interrupted
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "files", "diff", "json_stream", "merge_csv", "suggest_rename", "output_dir",
+            "unknown_only", "dump_lyrics"
+        ]
+    )]
+    follow_file_list: Option<PathBuf>,
+
+    /// Print each file's lyrics instead of a report, as LRC when only
+    /// synchronized lyrics are present
+    #[arg(
+        long,
+        conflicts_with_all = ["diff", "json_stream", "suggest_rename", "output_dir", "unknown_only"]
+    )]
+    dump_lyrics: bool,
+
+    /// With --output-dir, mirror the subdirectories of directory arguments
+    #[arg(long, requires = "output_dir")]
+    preserve_tree: bool,
+
+    /// Keep files with the same value of this field or tag together in the
+    /// report, groups in the order they first appear
+    #[arg(long, value_name = "FIELD", conflicts_with_all = ["diff", "json_stream", "unknown_only"])]
+    group_by: Option<String>,
+
+    /// With --group-by, write each group's report into this directory as
+    /// <value>.<ext> instead of printing one report
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "group_by",
+        conflicts_with_all = ["output", "output_dir", "suggest_rename", "follow_file_list"]
+    )]
+    group_output: Option<PathBuf>,
 }
 
-fn collect_from_stdin() -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    io::stdin()
-        .lock()
-        .lines()
-        .map(|line| {
-            let path = PathBuf::from(line?.trim());
-            if path.is_file() && is_audio_file(&path) {
-                Ok(Some(path))
-            } else {
-                if !path.exists() {
-                    eprintln!("Warning: File not found: {}", path.display());
-                }
-                Ok(None)
-            }
-        })
-        .collect::<Result<Vec<_>, _>>()
-        .map(|files| files.into_iter().flatten().collect())
+#[derive(Subcommand)]
+enum Command {
+    /// Show audio file technical metadata (the default)
+    Show(Box<Cli>),
+    /// Check each file's fields against expected values, failing the run on
+    /// any mismatch
+    Verify {
+        /// Require FIELD (an output field or tag) to equal VALUE; repeat for
+        /// several conditions, all of which must hold
+        #[arg(
+            long,
+            value_name = "FIELD=VALUE",
+            required = true,
+            value_parser = parse_expectation,
+            conflicts_with_all = ["json_stream", "unknown_only", "diff", "follow_file_list"]
+        )]
+        expect: Vec<(String, String)>,
+
+        #[command(flatten)]
+        cli: Box<Cli>,
+    },
+    /// Print a completion script for a shell to stdout
+    Completions { shell: clap_complete::Shell },
 }
 
-fn collect_audio_files(
-    paths: &[PathBuf],
-    recursive: bool,
-) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-    let mut files = Vec::new();
-
-    for path in paths {
-        if path.is_file() {
-            if is_audio_file(path) {
-                files.push(path.clone());
-            }
-        } else if path.is_dir() {
-            if recursive {
-                files.extend(
-                    walkdir::WalkDir::new(path)
-                        .into_iter()
-                        .filter_map(|e| e.ok())
-                        .filter(|e| e.file_type().is_file())
-                        .map(|e| e.path().to_path_buf())
-                        .filter(is_audio_file),
-                );
-            } else {
-                return Err(format!(
-                    "{} is a directory (use --recursive to process directories)",
-                    path.display()
-                )
-                .into());
-            }
-        } else {
-            return Err(format!("File not found: {}", path.display()).into());
+fn parse_expectation(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((field, value)) if !field.trim().is_empty() => {
+            Ok((field.trim().to_string(), value.to_string()))
         }
+        _ => Err(format!("'{s}' is not FIELD=VALUE")),
     }
-
-    Ok(files)
 }
 
-fn is_audio_file(path: &PathBuf) -> bool {
-    path.extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext_str| {
-            matches!(
-                ext_str.to_lowercase().as_str(),
-                "mp3" | "flac" | "ogg" | "wav" | "aac" | "m4a" | "wma"
-            )
-        })
-        .unwrap_or(false)
+/// A `--tag-rename` pair.
+fn parse_rename(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+            Ok((old.trim().to_string(), new.trim().to_string()))
+        }
+        _ => Err(format!("'{s}' is not OLD=NEW")),
+    }
 }
 
-fn process_file(path: &PathBuf) -> Result<AudioInfo, Box<dyn std::error::Error>> {
-    let file_size = fs::metadata(path)?.len();
-    let reader = Box::new(File::open(path)?);
-    let mss = MediaSourceStream::new(reader, Default::default());
-    
-    let mut hint = Hint::new();
-    if let Some(ext_str) = path.extension().and_then(|ext| ext.to_str()) {
-        hint.with_extension(ext_str);
+/// A `--key`: hex digits, optionally after `0x`, with whitespace ignored.
+/// Errors don't repeat the key.
+fn parse_key(s: &str) -> Result<Key, String> {
+    let hex: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let hex = hex.strip_prefix("0x").unwrap_or(&hex);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("the key is not hexadecimal".to_string());
     }
-
-    let probed = symphonia::default::get_probe().format(
-        &hint,
-        mss,
-        &FormatOptions::default(),
-        &MetadataOptions::default(),
-    )?;
-    let mut format = probed.format;
-
-    let track = format.tracks().first().ok_or("No supported audio track")?;
-    let params = &track.codec_params;
-
-    let mut info = AudioInfo {
-        file_path: path.display().to_string(),
-        sample_rate: params.sample_rate,
-        channels: params.channels.map(|ch| ch.count() as u8),
-        duration_seconds: None,
-        avg_bitrate_kbps: None,
-        tags: Vec::new(),
-        file_size_bytes: file_size,
-        codec: Some(params.codec.to_string()),
-    };
-
-    // Calculate duration and bitrate
-    if let (Some(time_base), Some(n_frames)) = (params.time_base, params.n_frames) {
-        let duration = time_base.calc_time(n_frames);
-        info.duration_seconds = Some(duration.seconds);
-        
-        let bitrate_bps = (file_size as f64 * 8.0) / (duration.seconds as f64);
-        info.avg_bitrate_kbps = Some((bitrate_bps / 1_000.0) as u32);
-    }
-
-    // Collect tags
-    info.tags = format
-        .metadata()
-        .current()
-        .iter()
-        .flat_map(|m| m.tags())
-        .map(|tag| (normalize_key(tag), tag.value.to_string()))
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err(format!(
+            "the key has {} hex digits, not a whole number of bytes",
+            hex.len()
+        ));
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
         .collect();
+    Ok(Key(bytes))
+}
 
-    Ok(info)
+/// A `--keyfile`: a file holding a `--key`.
+fn parse_keyfile(path: &str) -> Result<Key, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    parse_key(&text).map_err(|e| format!("{path}: {e}"))
 }
 
-fn output_text(infos: &[AudioInfo], basic_only: bool) {
-    for (i, info) in infos.iter().enumerate() {
-        if i > 0 {
-            println!();
-        }
+/// The most worker threads `--jobs` defaults to. Runs mostly wait on file
+/// reads, which more threads than this seldom speed up.
+const MAX_DEFAULT_JOBS: usize = 8;
 
-        println!("file: {}", info.file_path);
+impl Cli {
+    /// `--jobs`, or else one per logical CPU, up to `MAX_DEFAULT_JOBS`.
+    fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |n| n.get().min(MAX_DEFAULT_JOBS))
+        })
+    }
 
-        if let Some(codec) = &info.codec {
-            println!("codec: {codec}");
+    fn on_error(&self) -> OnError {
+        match self.on_error {
+            Some(mode) => mode,
+            None if self.keep_going || self.unknown_only => OnError::Continue,
+            None => OnError::Abort,
         }
+    }
 
-        if let Some(sr) = info.sample_rate {
-            println!("sample_rate: {sr}");
+    fn walk(&self) -> Walk {
+        Walk {
+            recursive: self.recursive,
+            sorted: !self.no_sort,
+            order: match self.walk_order {
+                Traversal::Dfs => WalkOrder::DepthFirst,
+                Traversal::Bfs => WalkOrder::BreadthFirst,
+            },
         }
+    }
 
-        if let Some(ch) = info.channels {
-            println!("channels: {ch}");
+    fn options(&self) -> Options {
+        Options {
+            loudness: self.loudness,
+            peaks: self.peaks,
+            spectral: self.spectral,
+            resample_check: self.resample_check,
+            verify_md5: self.verify_md5,
+            verify_duration: self.verify_duration,
+            check: self.check,
+            detect_bpm: self.detect_bpm,
+            classify: self.classify,
+            decode_info: self.decode_info,
+            seek_test: self.seek_test,
+            explain: self.explain,
+            sort_tags: self.sort_tags,
+            duplicate_tags: match self.dedupe_tags {
+                None => DuplicateTags::Keep,
+                Some(DedupeTags::First) => DuplicateTags::First,
+                Some(DedupeTags::Last) => DuplicateTags::Last,
+                Some(DedupeTags::Join) => DuplicateTags::Join,
+            },
+            unicode_form: self.normalize_unicode.map(|form| match form {
+                NormalizeUnicode::Nfc => UnicodeForm::Nfc,
+                NormalizeUnicode::Nfd => UnicodeForm::Nfd,
+            }),
+            retries: self.retries,
+            art_data: self.extract_art.is_some() || self.embed_art,
+            input_format: self.input_format.clone(),
+            decrypt_helper: self.decrypt_helper.clone(),
+            key: self.key.clone().or_else(|| self.keyfile.clone()),
+            track: self.track,
+            binary_tags: self.include_binary_tags,
         }
+    }
 
-        match info.duration_seconds {
-            Some(duration) => println!("duration: {duration:.2}s"),
-            None => println!("duration: unknown"),
+    fn key_case(&self) -> KeyCase {
+        if self.uppercase_keys {
+            KeyCase::Upper
+        } else {
+            self.key_case
         }
+    }
+}
 
-        if let Some(bitrate) = info.avg_bitrate_kbps {
-            println!("avg_bitrate_kbps: {bitrate}");
+/// Set by Ctrl-C during `--json-stream` so processing stops taking new files and
+/// the array can still be closed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The `--errors-to` file, once opened.
+static ERROR_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Write a line of error or warning output: to the `--errors-to` file when
+/// there is one, otherwise to stderr.
+macro_rules! report {
+    ($($arg:tt)*) => {
+        report_line(format_args!($($arg)*))
+    };
+}
+
+fn report_line(line: std::fmt::Arguments) {
+    if let Some(held) = HELD.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        held.reports.push(line.to_string());
+        return;
+    }
+    match ERROR_LOG.get() {
+        Some(log) => {
+            let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+            // Nowhere left to report a failure to write the error log
+            let _ = writeln!(log, "{line}");
         }
+        None => eprintln!("{line}"),
+    }
+}
 
-        println!("file_size_bytes: {}", info.file_size_bytes);
+/// Output held back by `--quiet-on-success` until the run is known to have
+/// failed.
+#[derive(Default)]
+struct Held {
+    stdout: Vec<u8>,
+    reports: Vec<String>,
+}
 
-        if !basic_only {
-            for (key, value) in &info.tags {
-                println!("{key}: {value}");
+/// Set while `--quiet-on-success` is holding output back.
+static HELD: Mutex<Option<Held>> = Mutex::new(None);
+
+/// Stdout while `--quiet-on-success` is holding output back.
+struct HeldStdout;
+
+impl Write for HeldStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match HELD.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(held) => {
+                held.stdout.extend_from_slice(buf);
+                Ok(buf.len())
             }
+            None => io::stdout().write(buf),
         }
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
-fn output_json(infos: &[AudioInfo]) {
-    let json_output = json!(infos
-        .iter()
-        .map(|info| json!({
-            "file_path": info.file_path,
-            "codec": info.codec,
-            "sample_rate": info.sample_rate,
-            "channels": info.channels,
-            "duration_seconds": info.duration_seconds,
-            "avg_bitrate_kbps": info.avg_bitrate_kbps,
-            "file_size_bytes": info.file_size_bytes,
-            "tags": info.tags.iter().cloned().collect::<std::collections::HashMap<_, _>>()
-        }))
-        .collect::<Vec<_>>());
-
-    println!("{}", serde_json::to_string_pretty(&json_output).unwrap());
-}
-
-fn output_csv(infos: &[AudioInfo]) {
-    println!("file_path,codec,sample_rate,channels,duration_seconds,avg_bitrate_kbps,file_size_bytes");
-    
-    for info in infos {
-        println!(
-            "{},{},{},{},{},{},{}",
-            info.file_path,
-            info.codec.as_deref().unwrap_or(""),
-            info.sample_rate.map_or(String::new(), |v| v.to_string()),
-            info.channels.map_or(String::new(), |v| v.to_string()),
-            info.duration_seconds.map_or(String::new(), |v| format!("{v:.2}")),
-            info.avg_bitrate_kbps.map_or(String::new(), |v| v.to_string()),
-            info.file_size_bytes,
-        );
+/// Stop holding output back and print what was held: messages first, as
+/// they would have come before the report.
+fn release_held() {
+    let held = HELD.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let Some(held) = held else {
+        return;
+    };
+    for line in &held.reports {
+        report!("{line}");
     }
+    let mut stdout = io::stdout().lock();
+    // Failing to print after a failed run changes nothing about the exit status
+    let _ = stdout.write_all(&held.stdout).and_then(|_| stdout.flush());
 }
 
-fn main() {
-    let cli = Cli::parse();
+/// Results kept by `--cache`, keyed by canonical path.
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    /// The analysis options the results were produced with
+    options: String,
+    files: BTreeMap<String, CacheEntry>,
+}
 
-    let use_stdin = cli.files.is_empty();
-    
-    if use_stdin && io::stdin().is_terminal() {
-        if !cli.quiet {
-            eprintln!("Error: No files provided and no data available on stdin");
-        }
-        process::exit(1);
+/// A file's result, valid for as long as its size and modification time are
+/// unchanged.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    mtime_ns: u64,
+    size: u64,
+    info: AudioInfo,
+}
+
+/// The `--cache` file, once loaded.
+static CACHE: OnceLock<Mutex<CacheFile>> = OnceLock::new();
+
+/// Load the `--cache` file, starting afresh when it doesn't exist yet, can't
+/// be read, or was written with different analysis options.
+fn load_cache(path: &Path, cli: &Cli) {
+    let analysis = cli.options();
+    let mut options = format!("{analysis:?}");
+    // Key's Debug hides the bytes, so tell keys apart by a digest of them
+    if let Some(key) = &analysis.key {
+        options.push_str(&format!(" key={}", blake3::hash(&key.0).to_hex()));
     }
+    let loaded = match fs::read(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        read => read
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                serde_json::from_slice::<CacheFile>(&bytes).map_err(|e| e.to_string())
+            })
+            .inspect_err(|e| {
+                if !cli.quiet {
+                    report!("Warning: ignoring unreadable cache {}: {e}", path.display());
+                }
+            })
+            .ok(),
+    };
+    let cache = loaded
+        .filter(|cache| cache.options == options)
+        .unwrap_or(CacheFile {
+            options,
+            ..Default::default()
+        });
+    let _ = CACHE.set(Mutex::new(cache));
+}
 
-    let files = if use_stdin {
-        collect_from_stdin()
-    } else {
-        collect_audio_files(&cli.files, cli.recursive)
+/// Write the `--cache` file back, replacing the old one only once the new one
+/// is complete.
+fn save_cache(path: &Path, cli: &Cli) {
+    let Some(cache) = CACHE.get() else {
+        return;
     };
+    let cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let saved = tempfile::NamedTempFile::new_in(dir)
+        .and_then(|mut file| {
+            serde_json::to_writer(BufWriter::new(file.as_file_mut()), &*cache)?;
+            Ok(file)
+        })
+        .and_then(|file| file.persist(path).map_err(|e| e.error));
+    if let Err(e) = saved
+        && !cli.quiet
+    {
+        report!("Warning: cannot write cache {}: {e}", path.display());
+    }
+}
 
-    let files = match files {
-        Ok(files) => files,
-        Err(e) => {
-            if !cli.quiet {
-                eprintln!("Error: {e}");
-            }
-            process::exit(1);
-        }
+/// The cache key and validity stamp for a file, if it can be stat'd.
+fn cache_stamp(path: &Path) -> Option<(String, u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    Some((
+        key.to_string_lossy().into_owned(),
+        mtime.as_nanos() as u64,
+        meta.len(),
+    ))
+}
+
+/// Analyze `input`, reusing the `--cache` result when the file hasn't
+/// changed since it was stored.
+fn analyze_cached(input: &Input, options: &Options) -> Result<AudioInfo, AudioMetaError> {
+    // Images aren't cached, so runs that extract them always read the files
+    let (Some(cache), Input::File(path)) = (CACHE.get().filter(|_| !options.art_data), input)
+    else {
+        return audiometa::analyze_input(input, options);
+    };
+    let Some((key, mtime_ns, size)) = cache_stamp(path) else {
+        return audiometa::analyze_input(input, options);
+    };
+    if let Some(entry) = cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .files
+        .get(&key)
+        && entry.mtime_ns == mtime_ns
+        && entry.size == size
+    {
+        return Ok(entry.info.clone());
+    }
+
+    let info = audiometa::analyze_input(input, options)?;
+    let entry = CacheEntry {
+        mtime_ns,
+        size,
+        info: info.clone(),
     };
+    cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .files
+        .insert(key, entry);
+    Ok(info)
+}
 
-    if files.is_empty() {
-        if !cli.quiet {
-            eprintln!("Error: No audio files found");
+impl OutputFormat {
+    /// Resolve `Auto` against the output path's extension.
+    fn resolve(&self, output: Option<&Path>) -> OutputFormat {
+        match self {
+            OutputFormat::Auto => output
+                .and_then(|p| p.extension())
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| match ext.to_lowercase().as_str() {
+                    "json" => Some(OutputFormat::Json),
+                    "ndjson" | "jsonl" => Some(OutputFormat::Ndjson),
+                    "csv" => Some(OutputFormat::Csv),
+                    "md" => Some(OutputFormat::Md),
+                    "html" | "htm" => Some(OutputFormat::Html),
+                    "parquet" => Some(OutputFormat::Parquet),
+                    _ => None,
+                })
+                .unwrap_or(OutputFormat::Text),
+            other => other.clone(),
         }
-        process::exit(1);
     }
 
-    let mut results = Vec::new();
-    let mut had_errors = false;
+    /// File extension for a report in this (resolved) format.
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Md => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Auto | OutputFormat::Text | OutputFormat::Table => "txt",
+        }
+    }
+}
 
-    for file in &files {
-        match process_file(file) {
-            Ok(info) => results.push(info),
-            Err(e) => {
-                had_errors = true;
-                if !cli.quiet {
-                    eprintln!("Error with {}: {e}", file.display());
-                }
-                if !cli.keep_going {
-                    process::exit(1);
+/// The `--tag-contains` and `--tag-matches` filters, prepared once up front.
+struct TagFilter {
+    /// Tag key to restrict to, and the lowercased text to look for
+    contains: Option<(Option<String>, String)>,
+    matches: Option<Regex>,
+    /// Tag keys of which a file must lack at least one
+    missing: Vec<String>,
+}
+
+impl TagFilter {
+    fn new(cli: &Cli) -> Result<Self, regex::Error> {
+        let contains = cli
+            .tag_contains
+            .as_ref()
+            .map(|arg| match arg.split_once('=') {
+                Some((key, text)) if !key.is_empty() => {
+                    (Some(key.to_string()), text.to_lowercase())
                 }
-            }
+                _ => (None, arg.to_lowercase()),
+            });
+        let matches = cli.tag_matches.as_deref().map(Regex::new).transpose()?;
+
+        Ok(TagFilter {
+            contains,
+            matches,
+            missing: cli.only_missing.clone(),
+        })
+    }
+
+    /// Whether a file passes every filter that was given.
+    fn keep(&self, info: &AudioInfo) -> bool {
+        let contains = self.contains.as_ref().is_none_or(|(key, text)| {
+            info.tags
+                .iter()
+                .filter(|(k, _)| key.as_ref().is_none_or(|key| k.eq_ignore_ascii_case(key)))
+                .any(|(_, v)| v.to_lowercase().contains(text))
+        });
+        let matches = self
+            .matches
+            .as_ref()
+            .is_none_or(|regex| info.tags.iter().any(|(_, v)| regex.is_match(v)));
+        let missing = self.missing.is_empty()
+            || self
+                .missing
+                .iter()
+                .any(|key| !info.tags.iter().any(|(k, _)| k.eq_ignore_ascii_case(key)));
+
+        contains && matches && missing
+    }
+}
+
+/// The path as it should appear in `file_path`, per the path display options.
+fn display_path(path: &Path, cli: &Cli) -> String {
+    if cli.basename {
+        if let Some(name) = path.file_name() {
+            return name.to_string_lossy().into_owned();
+        }
+    } else if cli.absolute {
+        if let Ok(abs) = fs::canonicalize(path) {
+            return abs.display().to_string();
+        }
+    } else if let Some(base) = &cli.relative_to
+        && let (Ok(base), Ok(abs)) = (fs::canonicalize(base), fs::canonicalize(path))
+        && let Ok(rel) = abs.strip_prefix(&base)
+    {
+        return rel.display().to_string();
+    }
+
+    path.display().to_string()
+}
+
+/// The accepted extension set: `--ext` plus `--add-ext`, minus `--exclude-ext`.
+fn accepted_extensions(cli: &Cli) -> Vec<String> {
+    let normalize = |ext: &String| ext.trim_start_matches('.').to_lowercase();
+    let excluded: Vec<String> = cli.exclude_ext.iter().map(normalize).collect();
+
+    let mut exts: Vec<String> = Vec::new();
+    for ext in cli.ext.iter().chain(&cli.add_ext).map(normalize) {
+        if !excluded.contains(&ext) && !exts.contains(&ext) {
+            exts.push(ext);
         }
     }
+    exts
+}
+
+/// A `--wrap-width`: a column count, or `auto` for the terminal's width as
+/// the shell reports it in `COLUMNS` (80 when it doesn't).
+fn parse_wrap_width(s: &str) -> Result<usize, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        let columns = env::var("COLUMNS").ok().and_then(|c| c.trim().parse().ok());
+        return Ok(columns.filter(|&c| c > 0).unwrap_or(80));
+    }
+    match s.trim().parse() {
+        Ok(0) | Err(_) => Err(format!("'{s}' is not a positive column count or 'auto'")),
+        Ok(width) => Ok(width),
+    }
+}
+
+/// Parse a byte count with an optional K, M, G or T suffix, as in `500M`.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let shift = match s[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        suffix => return Err(format!("unknown size suffix '{suffix}'")),
+    };
+    let count: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid size '{s}'"))?;
+    count
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{s}' is too large"))
+}
+
+/// A `--bin-size`: whole seconds, or minutes or hours with an `m` or `h` suffix.
+fn parse_bin_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let scale = match s[digits.len()..].to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        suffix => return Err(format!("unknown duration suffix '{suffix}'")),
+    };
+    match digits.trim().parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!("'{s}' is not a positive duration")),
+        Ok(count) => count
+            .checked_mul(scale)
+            .ok_or_else(|| format!("duration '{s}' is too large")),
+    }
+}
+
+/// The generator behind `--sample` (SplitMix64), small enough to keep here so
+/// a seed picks the same files on every platform and release.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((u128::from(self.next()) * u128::from(n)) >> 64) as u64
+    }
+}
 
-    if !results.is_empty() {
-        match cli.format {
-            OutputFormat::Text => output_text(&results, cli.basic),
-            OutputFormat::Json => output_json(&results),
-            OutputFormat::Csv => output_csv(&results),
+/// A seed for `--sample` when none is given.
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    nanos ^ u64::from(process::id()).rotate_left(32)
+}
+
+/// Pick `count` of `items` uniformly at random, holding no more than that
+/// many at a time (reservoir sampling), and return them in their original
+/// order.
+fn sample<T>(items: impl Iterator<Item = T>, count: usize, rng: &mut SplitMix64) -> Vec<T> {
+    let mut reservoir: Vec<(usize, T)> = Vec::new();
+    for (i, item) in items.enumerate() {
+        if i < count {
+            reservoir.push((i, item));
+        } else {
+            let j = rng.below(i as u64 + 1) as usize;
+            if j < count {
+                reservoir[j] = (i, item);
+            }
         }
     }
+    reservoir.sort_by_key(|(i, _)| *i);
+    reservoir.into_iter().map(|(_, item)| item).collect()
+}
 
-    if had_errors {
-        process::exit(1);
+/// Whether a file lies within `--min-file-size` and `--max-file-size`. Files
+/// outside the bounds are skipped with a warning; URLs and files whose size
+/// can't be read are left for processing to deal with.
+fn size_allowed(input: &Input, cli: &Cli) -> bool {
+    if cli.max_file_size.is_none() && cli.min_file_size.is_none() {
+        return true;
     }
-}
\ No newline at end of file
+    let Input::File(path) = input else {
+        return true;
+    };
+    let Ok(size) = fs::metadata(path).map(|m| m.len()) else {
+        return true;
+    };
+
+    let reason = if cli.max_file_size.is_some_and(|max| size > max) {
+        "larger than --max-file-size"
+    } else if cli.min_file_size.is_some_and(|min| size < min) {
+        "smaller than --min-file-size"
+    } else {
+        return true;
+    };
+    if !cli.quiet {
+        report!(
+            "Warning: Skipping {} ({size} bytes, {reason})",
+            path.display()
+        );
+    }
+    false
+}
+
+/// Whether `--codec-only` lets a file through. A family name like `pcm` also
+/// matches its variants (`pcm_s16le`, ...).
+fn codec_selected(info: &AudioInfo, codecs: &[String]) -> bool {
+    if codecs.is_empty() {
+        return true;
+    }
+    let Some(codec) = &info.codec else {
+        return false;
+    };
+    let family = codec.split('_').next().unwrap_or(codec);
+    codecs
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(codec) || c.eq_ignore_ascii_case(family))
+}
+
+/// How to name `input` in the output: local paths (archives included) per the
+/// path display options, URLs as given.
+fn input_path(input: &Input, cli: &Cli) -> String {
+    match input {
+        Input::File(path) => display_path(path, cli),
+        Input::Url(url) => url.clone(),
+        Input::Archived(entry) => format!("{}!{}", display_path(&entry.archive, cli), entry.name),
+    }
+}
+
+/// What unsupported codec or format a failure is down to, for
+/// `--on-error skip-codec`: the codec when it is known, else the extension.
+fn unsupported_kind(error: &AudioMetaError, input: &Input) -> Option<String> {
+    match error {
+        AudioMetaError::Unsupported(name) => Some(name.to_string()),
+        e if e.is_unsupported() => {
+            let ext = match input {
+                Input::File(path) => path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().into_owned()),
+                Input::Url(url) => url_extension(url).map(str::to_string),
+                Input::Archived(entry) => Path::new(&entry.name)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().into_owned()),
+            };
+            Some(format!(".{}", ext.unwrap_or_default().to_lowercase()))
+        }
+        _ => None,
+    }
+}
+
+/// Analyze one input, reporting local paths per the path display options.
+fn process_input(input: &Input, cli: &Cli) -> Result<AudioInfo, AudioMetaError> {
+    let mut info = analyze_cached(input, &cli.options())?;
+    info.file_path = input_path(input, cli);
+    if !cli.tag_rename.is_empty() {
+        rename_tags(&mut info.tags, &cli.tag_rename);
+    }
+    if !cli.exclude_tag.is_empty() {
+        info.tags
+            .retain(|(key, _)| !cli.exclude_tag.iter().any(|k| k.eq_ignore_ascii_case(key)));
+    }
+    if cli.tag_value_max_len.is_some() {
+        for (_, value) in &mut info.tags {
+            *value = truncate(value, cli.tag_value_max_len);
+        }
+    }
+    Ok(info)
+}
+
+/// Apply `--tag-rename` to `tags`. Tags that end up under a renamed key are
+/// merged into one entry, where that key first appears, with their values
+/// joined by `; `.
+fn rename_tags(tags: &mut Vec<(String, String)>, renames: &[(String, String)]) {
+    let mut renamed: Vec<(String, String)> = Vec::with_capacity(tags.len());
+    for (key, value) in tags.drain(..) {
+        let key = match renames
+            .iter()
+            .find(|(old, _)| old.eq_ignore_ascii_case(&key))
+        {
+            Some((_, new)) => new.clone(),
+            None => key,
+        };
+        if renames.iter().any(|(_, new)| *new == key)
+            && let Some((_, kept)) = renamed.iter_mut().find(|(k, _)| *k == key)
+        {
+            if !kept.split("; ").any(|v| v == value) {
+                kept.push_str("; ");
+                kept.push_str(&value);
+            }
+            continue;
+        }
+        renamed.push((key, value));
+    }
+    *tags = renamed;
+}
+
+/// Process `files` on `cli.jobs()` worker threads, handing each result to
+/// `handle` in input order as soon as it and everything before it are done.
+fn process_files<'a>(
+    files: &'a [Input],
+    cli: &Cli,
+    mut handle: impl FnMut(&'a Input, Result<AudioInfo, AudioMetaError>),
+) {
+    let jobs = cli.jobs();
+    if jobs <= 1 {
+        for file in files {
+            if INTERRUPTED.load(Ordering::Relaxed) {
+                break;
+            }
+            handle(file, process_input(file, cli));
+        }
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(files.len()) {
+            let tx = tx.clone();
+            let next = &next;
+            scope.spawn(move || {
+                while !INTERRUPTED.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    let result = process_input(file, cli);
+                    if tx.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next_out = 0;
+        for (index, result) in rx {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next_out) {
+                handle(&files[next_out], result);
+                next_out += 1;
+            }
+        }
+    });
+}
+
+/// Writes a JSON array one element at a time, flushing after each so readers
+/// see records as files complete.
+struct JsonArrayStream {
+    out: Box<dyn Write>,
+    empty: bool,
+    batch: usize,
+    pending: usize,
+}
+
+impl JsonArrayStream {
+    /// Flush after every `batch` objects.
+    fn new(mut out: Box<dyn Write>, batch: usize) -> io::Result<Self> {
+        write!(out, "[")?;
+        out.flush()?;
+        Ok(JsonArrayStream {
+            out,
+            empty: true,
+            batch: batch.max(1),
+            pending: 0,
+        })
+    }
+
+    fn push(&mut self, value: &serde_json::Value) -> io::Result<()> {
+        let separator = if self.empty { "" } else { "," };
+        self.empty = false;
+        write!(self.out, "{separator}\n  {value}")?;
+        self.pending += 1;
+        if self.pending < self.batch {
+            return Ok(());
+        }
+        self.pending = 0;
+        self.out.flush()
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        writeln!(self.out, "{}]", if self.empty { "" } else { "\n" })?;
+        self.out.flush()
+    }
+}
+
+fn output_text(out: &mut dyn Write, infos: &[AudioInfo], cli: &Cli) -> io::Result<()> {
+    let (basic_only, verbose, quiet) = (cli.basic, cli.verbose, cli.quiet);
+    let key_case = cli.key_case();
+    let sep = cli.text_sep.as_str();
+    for (i, info) in infos.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+
+        writeln!(out, "file{sep}{}", info.file_path)?;
+
+        if let Some(codec) = &info.codec {
+            writeln!(out, "codec{sep}{codec}")?;
+        }
+
+        if let Some(profile) = &info.codec_profile {
+            writeln!(out, "codec_profile{sep}{profile}")?;
+        }
+
+        if let Some(container) = &info.container {
+            writeln!(out, "container{sep}{container}")?;
+        }
+
+        if let Some(drm) = info.drm {
+            writeln!(out, "drm{sep}{drm}")?;
+        }
+
+        // Only worth a line when there is a choice of track
+        if info.track_count > 1 {
+            writeln!(
+                out,
+                "audio_track{sep}{} of {}",
+                info.track_index, info.track_count
+            )?;
+        }
+
+        if let Some(sr) = info.sample_rate {
+            writeln!(out, "sample_rate{sep}{sr}")?;
+        }
+
+        if let Some(sr) = info.original_sample_rate {
+            writeln!(out, "original_sample_rate{sep}{sr}")?;
+        }
+
+        if let Some(ch) = info.channels {
+            writeln!(out, "channels{sep}{ch}")?;
+        }
+
+        if let Some(bits) = info.bits_per_sample {
+            writeln!(out, "bits_per_sample{sep}{bits}")?;
+        }
+
+        match info.duration_seconds {
+            Some(duration) => writeln!(out, "duration{sep}{duration:.2}s")?,
+            None => writeln!(out, "duration{sep}unknown")?,
+        }
+
+        if let Some(declared) = info.declared_duration_seconds {
+            writeln!(out, "declared_duration{sep}{declared:.3}s")?;
+        }
+
+        if let Some(decoded) = info.decoded_duration_seconds {
+            writeln!(out, "decoded_duration{sep}{decoded:.3}s")?;
+        }
+
+        if let Some(bitrate) = info.avg_bitrate_kbps {
+            writeln!(out, "avg_bitrate_kbps{sep}{bitrate}")?;
+        }
+
+        writeln!(out, "file_size_bytes{sep}{}", info.file_size_bytes)?;
+
+        if let Some(modified) = &info.modified_time {
+            writeln!(out, "modified_time{sep}{modified}")?;
+        }
+
+        if let Some(created) = &info.created_time {
+            writeln!(out, "created_time{sep}{created}")?;
+        }
+
+        if let Some(ratio) = info.compression_ratio {
+            writeln!(out, "compression_ratio{sep}{ratio:.3}")?;
+        }
+
+        if let Some(vendor) = &info.vendor {
+            writeln!(out, "vendor{sep}{vendor}")?;
+        }
+
+        if let Some(lufs) = info.integrated_lufs {
+            writeln!(out, "integrated_lufs{sep}{lufs:.2}")?;
+        }
+
+        if let Some(lra) = info.loudness_range_lu {
+            writeln!(out, "loudness_range_lu{sep}{lra:.2}")?;
+        }
+
+        if let Some(md5) = &info.flac_audio_md5 {
+            writeln!(out, "flac_audio_md5{sep}{md5}")?;
+        }
+
+        if let Some(ok) = info.flac_md5_ok {
+            writeln!(out, "flac_md5_ok{sep}{ok}")?;
+        }
+
+        if let Some(format) = &info.sample_format {
+            writeln!(out, "sample_format{sep}{format}")?;
+        }
+
+        if !info.channel_order.is_empty() {
+            writeln!(out, "channel_order{sep}{}", info.channel_order.join(", "))?;
+        }
+
+        if let Some(frames) = info.max_frames_per_packet {
+            writeln!(out, "max_frames_per_packet{sep}{frames}")?;
+        }
+
+        if let Some(seekable) = info.seekable {
+            writeln!(out, "seekable{sep}{seekable}")?;
+        }
+
+        if let Some(accuracy) = &info.seek_accuracy {
+            writeln!(out, "seek_accuracy{sep}{accuracy}")?;
+        }
+
+        if let Some(index) = info.has_seek_index {
+            writeln!(out, "has_seek_index{sep}{index}")?;
+        }
+
+        if let Some(errors) = info.decode_errors {
+            writeln!(out, "decode_errors{sep}{errors}")?;
+        }
+
+        if info.likely_truncated == Some(true) {
+            writeln!(out, "likely_truncated{sep}true")?;
+        }
+
+        if let Some(preset) = &info.lame_preset {
+            writeln!(out, "lame_preset{sep}{preset}")?;
+        }
+
+        if let Some(quality) = info.lame_vbr_quality {
+            writeln!(out, "lame_vbr_quality{sep}V{quality}")?;
+        }
+
+        if info.has_lyrics {
+            writeln!(out, "has_lyrics{sep}true")?;
+        }
+
+        for (name, value) in [
+            ("isrc", &info.isrc),
+            ("barcode", &info.barcode),
+            ("musicbrainz_trackid", &info.musicbrainz_trackid),
+            ("musicbrainz_albumid", &info.musicbrainz_albumid),
+        ] {
+            if let Some(value) = value {
+                writeln!(out, "{name}{sep}{value}")?;
+            }
+        }
+
+        if let Some(bpm) = info.bpm {
+            let estimated = if info.bpm_estimated == Some(true) {
+                " (estimated)"
+            } else {
+                ""
+            };
+            writeln!(out, "bpm{sep}{bpm}{estimated}")?;
+        }
+
+        if let Some(content_type) = &info.content_type {
+            writeln!(out, "content_type{sep}{content_type}")?;
+        }
+
+        if let Some(cutoff) = info.frequency_cutoff_hz {
+            writeln!(out, "frequency_cutoff_hz{sep}{cutoff:.0}")?;
+        }
+
+        if let Some(resampled) = info.likely_resampled {
+            writeln!(out, "likely_resampled{sep}{resampled}")?;
+        }
+
+        if let Some(peak) = info.sample_peak {
+            writeln!(out, "sample_peak{sep}{peak:.4}")?;
+        }
+
+        if !info.channel_peaks.is_empty() {
+            let peaks: Vec<_> = info
+                .channel_peaks
+                .iter()
+                .map(|p| format!("{p:.4}"))
+                .collect();
+            writeln!(out, "channel_peaks{sep}{}", peaks.join(", "))?;
+        }
+
+        writeln!(out, "tag_count{sep}{}", info.tag_count)?;
+        writeln!(out, "tag_bytes{sep}{}", info.tag_bytes)?;
+
+        if !info.metadata_blocks.is_empty() {
+            let blocks: Vec<_> = info
+                .metadata_blocks
+                .iter()
+                .map(|block| {
+                    format!(
+                        "{} ({})",
+                        block.format,
+                        if block.at_end { "end" } else { "start" }
+                    )
+                })
+                .collect();
+            writeln!(out, "metadata_blocks{sep}{}", blocks.join(", "))?;
+        }
+
+        if verbose && !info.chunks.is_empty() {
+            let chunks: Vec<_> = info
+                .chunks
+                .iter()
+                .map(|chunk| format!("'{}' ({})", chunk.id, chunk.size_bytes))
+                .collect();
+            writeln!(out, "chunks{sep}{}", chunks.join(", "))?;
+        }
+
+        for art in &info.art {
+            let size = match (art.width, art.height) {
+                (Some(width), Some(height)) => format!(" {width}x{height}"),
+                _ => String::new(),
+            };
+            writeln!(
+                out,
+                "art{sep}{} {}{size} ({} bytes)",
+                art.kind.as_deref().unwrap_or("picture"),
+                art.media_type,
+                art.size_bytes
+            )?;
+        }
+
+        if !quiet {
+            for warning in &info.warnings {
+                writeln!(out, "warning{sep}{warning}")?;
+            }
+        }
+
+        for (field, how) in &info.explain {
+            writeln!(out, "explain{sep}{field} = {how}")?;
+        }
+
+        if !basic_only {
+            for (key, value) in &info.tags {
+                let key = format!("{}{sep}", key_case.apply(key));
+                match cli.wrap_width {
+                    Some(width) => {
+                        writeln!(out, "{key}{}", wrap(value, key.chars().count(), width))?
+                    }
+                    None => writeln!(out, "{key}{value}")?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Wrap `value` at whitespace to fit lines of `width` characters, following
+/// a key `indent` characters wide; continuation lines hang under the value.
+/// Words longer than a line are left whole.
+fn wrap(value: &str, indent: usize, width: usize) -> String {
+    let room = width.saturating_sub(indent).max(1);
+    let mut lines = Vec::new();
+    for paragraph in value.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > room {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines.join(&format!("\n{}", " ".repeat(indent)))
+}
+
+fn info_json(info: &AudioInfo, key_case: KeyCase) -> serde_json::Value {
+    let mut record = json!({
+        "file_path": info.file_path,
+        "id": info.id,
+        "codec": info.codec,
+        "codec_profile": info.codec_profile,
+        "container": info.container,
+        "drm": info.drm,
+        "sample_rate": info.sample_rate,
+        "original_sample_rate": info.original_sample_rate,
+        "channels": info.channels,
+        "bits_per_sample": info.bits_per_sample,
+        "duration_seconds": info.duration_seconds,
+        "avg_bitrate_kbps": info.avg_bitrate_kbps,
+        "file_size_bytes": info.file_size_bytes,
+        "modified_time": info.modified_time,
+        "created_time": info.created_time,
+        "integrated_lufs": info.integrated_lufs,
+        "loudness_range_lu": info.loudness_range_lu,
+        "sample_peak": info.sample_peak,
+        "channel_peaks": (!info.channel_peaks.is_empty()).then_some(&info.channel_peaks),
+        "audio_data_offset": info.audio_data_offset,
+        "metadata_size_bytes": info.metadata_size_bytes,
+        "stream_count": info.stream_count,
+        "track_count": info.track_count,
+        "track_index": info.track_index,
+        "vendor": info.vendor,
+        "frequency_cutoff_hz": info.frequency_cutoff_hz,
+        "likely_resampled": info.likely_resampled,
+        "flac_audio_md5": info.flac_audio_md5,
+        "flac_md5_ok": info.flac_md5_ok,
+        "decode_errors": info.decode_errors,
+        "sample_format": info.sample_format,
+        "channel_order": info.channel_order,
+        "max_frames_per_packet": info.max_frames_per_packet,
+        "seekable": info.seekable,
+        "seek_accuracy": info.seek_accuracy,
+        "has_seek_index": info.has_seek_index,
+        "likely_truncated": info.likely_truncated,
+        "lame_preset": info.lame_preset,
+        "lame_vbr_quality": info.lame_vbr_quality,
+        "compression_ratio": info.compression_ratio,
+        "declared_duration_seconds": info.declared_duration_seconds,
+        "decoded_duration_seconds": info.decoded_duration_seconds,
+        "has_lyrics": info.has_lyrics,
+        "isrc": info.isrc,
+        "barcode": info.barcode,
+        "musicbrainz_trackid": info.musicbrainz_trackid,
+        "musicbrainz_albumid": info.musicbrainz_albumid,
+        "bpm": info.bpm,
+        "bpm_estimated": info.bpm_estimated,
+        "content_type": info.content_type,
+        "metadata_blocks": info
+            .metadata_blocks
+            .iter()
+            .map(|block| {
+                json!({
+                    "format": block.format,
+                    "location": if block.at_end { "end" } else { "start" },
+                    "size_bytes": block.size_bytes,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "metadata_at_end": info.metadata_at_end,
+        "chunks": info
+            .chunks
+            .iter()
+            .map(|chunk| json!({ "id": chunk.id, "size_bytes": chunk.size_bytes }))
+            .collect::<Vec<_>>(),
+        "tag_count": info.tag_count,
+        "tag_bytes": info.tag_bytes,
+        "art": info
+            .art
+            .iter()
+            .map(|art| {
+                json!({
+                    "type": art.kind,
+                    "media_type": art.media_type,
+                    "width": art.width,
+                    "height": art.height,
+                    "size_bytes": art.size_bytes,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "warnings": info.warnings,
+        "tags": info
+            .tags
+            .iter()
+            .map(|(k, v)| (key_case.apply(k), json!(v)))
+            .collect::<serde_json::Map<_, _>>()
+    });
+    if !info.explain.is_empty() {
+        record["_explain"] = info
+            .explain
+            .iter()
+            .map(|(field, how)| (field.clone(), json!(how)))
+            .collect();
+    }
+    record
+}
+
+/// Lift the `tags` object of an `info_json` record to top-level `tag.<key>`
+/// entries, which can't clash with the (dot-free) field names.
+fn flatten_json(record: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Object(object) = record else {
+        return record;
+    };
+
+    let mut flat = serde_json::Map::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("tags", serde_json::Value::Object(tags)) => {
+                flat.extend(tags.into_iter().map(|(k, v)| (format!("tag.{k}"), v)));
+            }
+            (_, value) => {
+                flat.insert(key, value);
+            }
+        }
+    }
+    serde_json::Value::Object(flat)
+}
+
+/// Replace every integer in `value` with its decimal string, for consumers
+/// (JavaScript) whose numbers lose precision above 2^53.
+fn integers_as_strings(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Number(n) if !n.is_f64() => *value = json!(n.to_string()),
+        serde_json::Value::Array(values) => values.iter_mut().for_each(integers_as_strings),
+        serde_json::Value::Object(object) => object.values_mut().for_each(integers_as_strings),
+        _ => {}
+    }
+}
+
+/// Sort the keys of every object in `value`, nested ones included.
+fn sort_keys(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(values) => values.iter_mut().for_each(sort_keys),
+        serde_json::Value::Object(object) => {
+            object.sort_keys();
+            object.values_mut().for_each(sort_keys);
+        }
+        _ => {}
+    }
+}
+
+/// A file's JSON record as written by the JSON outputs.
+fn record_json(info: &AudioInfo, cli: &Cli) -> serde_json::Value {
+    let record = info_json(info, cli.key_case());
+    let mut record = if cli.flatten {
+        flatten_json(record)
+    } else {
+        record
+    };
+    if cli.json_numbers_as_strings {
+        integers_as_strings(&mut record);
+    }
+    if cli.embed_art {
+        record["art_data_uri"] = json!(art_data_uri(info, cli.art_max_dim, cli.quiet));
+    }
+    if cli.json_sort_keys {
+        sort_keys(&mut record);
+    }
+    record
+}
+
+/// Picture media types a `data:` URI may name, as they are written.
+const ART_MEDIA_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+];
+
+/// The front cover, or else the first picture, as a `data:` URI, shrunk to
+/// fit `max_dim` pixels on either side. `None` without a picture, or when
+/// one too large can't be decoded to shrink it, which is noted unless
+/// `quiet`.
+fn art_data_uri(info: &AudioInfo, max_dim: Option<u32>, quiet: bool) -> Option<String> {
+    use base64::{Engine, prelude::BASE64_STANDARD};
+    use image::{ImageFormat, imageops::FilterType};
+
+    let art = info
+        .art
+        .iter()
+        .find(|art| art.kind.as_deref() == Some("front_cover"))
+        .or(info.art.first())?;
+    let uri = |media_type: &str, data: &[u8]| {
+        // The media type comes from the file, so only known ones are passed on
+        let media_type = ART_MEDIA_TYPES
+            .iter()
+            .find(|known| known.eq_ignore_ascii_case(media_type))
+            .unwrap_or(&"application/octet-stream");
+        format!("data:{media_type};base64,{}", BASE64_STANDARD.encode(data))
+    };
+    let too_large =
+        |max: u32| art.width.is_none_or(|w| w > max) || art.height.is_none_or(|h| h > max);
+    match max_dim.filter(|&max| too_large(max)) {
+        None => Some(uri(&art.media_type, &art.data)),
+        Some(max) => {
+            let shrink = || -> image::ImageResult<(ImageFormat, Vec<u8>)> {
+                let picture = image::load_from_memory(&art.data)?;
+                // PNG keeps any transparency; everything else becomes JPEG
+                let format = match image::guess_format(&art.data)? {
+                    ImageFormat::Png => ImageFormat::Png,
+                    _ => ImageFormat::Jpeg,
+                };
+                let picture = if picture.width() > max || picture.height() > max {
+                    picture.resize(max, max, FilterType::Lanczos3)
+                } else {
+                    picture
+                };
+                let picture = match format {
+                    ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(picture.to_rgb8()),
+                    _ => picture,
+                };
+                let mut data = Vec::new();
+                picture.write_to(&mut io::Cursor::new(&mut data), format)?;
+                Ok((format, data))
+            };
+            match shrink() {
+                Ok((format, data)) => Some(uri(format.to_mime_type(), &data)),
+                Err(e) => {
+                    if !quiet {
+                        report!(
+                            "Note: cannot downscale the picture in {}: {e}",
+                            info.file_path
+                        );
+                    }
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn output_json(out: &mut dyn Write, infos: &[AudioInfo], cli: &Cli) -> io::Result<()> {
+    let json_output = json!(
+        infos
+            .iter()
+            .map(|info| record_json(info, cli))
+            .collect::<Vec<_>>()
+    );
+
+    writeln!(
+        out,
+        "{}",
+        serde_json::to_string_pretty(&json_output).unwrap()
+    )
+}
+
+/// Flatten an `info_json` object into comparable `(field, value)` pairs, with
+/// tags keyed as `tag:<key>`.
+fn diff_fields(info: &AudioInfo, key_case: KeyCase) -> Vec<(String, serde_json::Value)> {
+    let serde_json::Value::Object(object) = info_json(info, key_case) else {
+        unreachable!("info_json always builds an object");
+    };
+
+    let mut fields = Vec::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("file_path" | "id" | "_explain", _) => {}
+            ("tags", serde_json::Value::Object(tags)) => {
+                fields.extend(tags.into_iter().map(|(k, v)| (format!("tag:{k}"), v)));
+            }
+            (_, value) => fields.push((key, value)),
+        }
+    }
+    fields
+}
+
+fn diff_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "(none)".to_string(),
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn output_diff(
+    out: &mut dyn Write,
+    a: &AudioInfo,
+    b: &AudioInfo,
+    diff_only: bool,
+    format: &OutputFormat,
+    key_case: KeyCase,
+) -> io::Result<()> {
+    let a_fields = diff_fields(a, key_case);
+    let b_fields = diff_fields(b, key_case);
+    let lookup = |fields: &[(String, serde_json::Value)], key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map_or(serde_json::Value::Null, |(_, v)| v.clone())
+    };
+
+    // Every field of `a` in order, then anything only `b` has
+    let mut keys: Vec<&String> = a_fields.iter().map(|(k, _)| k).collect();
+    keys.extend(
+        b_fields
+            .iter()
+            .map(|(k, _)| k)
+            .filter(|k| !a_fields.iter().any(|(ak, _)| ak == *k)),
+    );
+    let rows: Vec<_> = keys
+        .into_iter()
+        .map(|key| (key, lookup(&a_fields, key), lookup(&b_fields, key)))
+        .collect();
+
+    if let OutputFormat::Json = format {
+        let (same, changed): (Vec<_>, Vec<_>) = rows.iter().partition(|(_, av, bv)| av == bv);
+        let mut report = json!({
+            "a": a.file_path,
+            "b": b.file_path,
+            "changed": changed
+                .iter()
+                .map(|(field, av, bv)| json!({ "field": field, "a": av, "b": bv }))
+                .collect::<Vec<_>>(),
+        });
+        if !diff_only {
+            report["same"] = same
+                .iter()
+                .map(|(field, value, _)| json!({ "field": field, "value": value }))
+                .collect();
+        }
+        return writeln!(out, "{}", serde_json::to_string_pretty(&report).unwrap());
+    }
+
+    writeln!(out, "--- {}", a.file_path)?;
+    writeln!(out, "+++ {}", b.file_path)?;
+    for (key, av, bv) in &rows {
+        if av != bv {
+            writeln!(out, "- {key}: {}", diff_value(av))?;
+            writeln!(out, "+ {key}: {}", diff_value(bv))?;
+        } else if !diff_only {
+            writeln!(out, "  {key}: {}", diff_value(av))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Every field `--fields` can select, in `info_json` order, with its JSON
+/// type and a description for `--list-fields`.
+const FIELDS: &[(&str, &str, &str)] = &[
+    (
+        "file_path",
+        "string",
+        "Path (or URL) of the file, per the path display options",
+    ),
+    (
+        "id",
+        "string",
+        "Short hash of the canonical path, stable across runs",
+    ),
+    (
+        "codec",
+        "string",
+        "Codec short name, e.g. flac or pcm_s16le",
+    ),
+    (
+        "codec_profile",
+        "string",
+        "Codec profile where the file records one, e.g. LC or HE-AAC",
+    ),
+    (
+        "container",
+        "string",
+        "Container format, from the file's magic bytes",
+    ),
+    (
+        "drm",
+        "boolean",
+        "Whether the audio is DRM-protected (FairPlay, Audible AAX, CENC); MP4 only",
+    ),
+    ("sample_rate", "integer", "Sample rate in Hz"),
+    (
+        "original_sample_rate",
+        "integer",
+        "Rate of the encoded source when it differs from sample_rate (Opus)",
+    ),
+    ("channels", "integer", "Number of channels"),
+    (
+        "bits_per_sample",
+        "integer",
+        "Bit depth, for PCM and lossless codecs",
+    ),
+    ("duration_seconds", "integer", "Duration in whole seconds"),
+    (
+        "avg_bitrate_kbps",
+        "integer",
+        "Whole-file size over duration, in kbit/s",
+    ),
+    ("file_size_bytes", "integer", "File size in bytes"),
+    (
+        "modified_time",
+        "string",
+        "Last modification time, RFC 3339 in UTC (local files)",
+    ),
+    (
+        "created_time",
+        "string",
+        "Creation time where the platform records it, RFC 3339 in UTC",
+    ),
+    (
+        "integrated_lufs",
+        "number",
+        "EBU R128 integrated loudness (--loudness)",
+    ),
+    (
+        "loudness_range_lu",
+        "number",
+        "EBU R128 loudness range (--loudness)",
+    ),
+    (
+        "sample_peak",
+        "number",
+        "Highest absolute sample value (--peaks)",
+    ),
+    (
+        "channel_peaks",
+        "array",
+        "Highest absolute sample value per channel (--peaks)",
+    ),
+    (
+        "audio_data_offset",
+        "integer",
+        "Byte offset where the audio data starts",
+    ),
+    (
+        "metadata_size_bytes",
+        "integer",
+        "Bytes outside the audio data: leading headers and tags, plus trailing tags",
+    ),
+    (
+        "stream_count",
+        "integer",
+        "Number of tracks in the container",
+    ),
+    ("track_count", "integer", "Number of audio tracks"),
+    (
+        "track_index",
+        "integer",
+        "Which audio track is reported, counting from 1 (--track)",
+    ),
+    (
+        "vendor",
+        "string",
+        "Encoder vendor string from a Vorbis comment header",
+    ),
+    (
+        "frequency_cutoff_hz",
+        "number",
+        "Estimated frequency cutoff (--spectral)",
+    ),
+    (
+        "likely_resampled",
+        "boolean",
+        "Whether a steep low-pass suggests a sample rate conversion (--resample-check)",
+    ),
+    (
+        "flac_audio_md5",
+        "string",
+        "MD5 of the unencoded audio from FLAC STREAMINFO",
+    ),
+    (
+        "flac_md5_ok",
+        "boolean",
+        "Whether the decoded audio matches flac_audio_md5 (--verify-md5)",
+    ),
+    (
+        "sample_format",
+        "string",
+        "Sample format the decoder produces, e.g. s16 or f32 (--decode-info)",
+    ),
+    (
+        "channel_order",
+        "array",
+        "Order of the channels within a frame (--decode-info)",
+    ),
+    (
+        "max_frames_per_packet",
+        "integer",
+        "Most frames a single decoded packet holds (--decode-info)",
+    ),
+    (
+        "seekable",
+        "boolean",
+        "Whether the format reader can seek within the track (--seek-test)",
+    ),
+    (
+        "seek_accuracy",
+        "string",
+        "How precisely the reader seeks by time: accurate, coarse or none (--seek-test)",
+    ),
+    (
+        "has_seek_index",
+        "boolean",
+        "Whether the file has a seek index (FLAC SEEKTABLE, MP3 TOC, MP4 sample tables, Matroska Cues)",
+    ),
+    (
+        "decode_errors",
+        "integer",
+        "Corrupt packets skipped while decoding (--check or any decode pass)",
+    ),
+    (
+        "likely_truncated",
+        "boolean",
+        "Whether the file holds less audio than its header promises",
+    ),
+    (
+        "lame_preset",
+        "string",
+        "LAME preset from the LAME tag, e.g. V0 or ABR 192",
+    ),
+    (
+        "lame_vbr_quality",
+        "integer",
+        "LAME VBR quality, 0 (V0) to 9 (V9)",
+    ),
+    (
+        "compression_ratio",
+        "number",
+        "File size over the raw PCM size, for lossless codecs",
+    ),
+    (
+        "declared_duration_seconds",
+        "number",
+        "Duration the header declares (--verify-duration)",
+    ),
+    (
+        "decoded_duration_seconds",
+        "number",
+        "Duration of the audio actually decoded (--verify-duration)",
+    ),
+    (
+        "has_lyrics",
+        "boolean",
+        "Whether the file has unsynchronized or synchronized lyrics",
+    ),
+    (
+        "isrc",
+        "string",
+        "ISRC from the tags, in compact form (invalid codes are left out with a warning)",
+    ),
+    (
+        "barcode",
+        "string",
+        "Release barcode (UPC/EAN) from the tags",
+    ),
+    (
+        "musicbrainz_trackid",
+        "string",
+        "MusicBrainz track ID from the tags",
+    ),
+    (
+        "musicbrainz_albumid",
+        "string",
+        "MusicBrainz album (release) ID from the tags",
+    ),
+    (
+        "bpm",
+        "number",
+        "Tempo from the BPM tag, or estimated from the audio (--detect-bpm)",
+    ),
+    (
+        "bpm_estimated",
+        "boolean",
+        "Whether bpm was estimated rather than read from a tag",
+    ),
+    (
+        "content_type",
+        "string",
+        "speech, music or unknown, guessed from a decoded stretch of audio (--classify)",
+    ),
+    (
+        "metadata_blocks",
+        "array",
+        "Tag blocks (id3v2, id3v1, ape, vorbis_comment, flac_picture, mp4, riff_*) with location and size",
+    ),
+    (
+        "metadata_at_end",
+        "boolean",
+        "Whether any tag block comes after the audio data",
+    ),
+    (
+        "chunks",
+        "array",
+        "Top-level chunks of a WAV file (fmt, data, LIST, bext, ...) with their sizes",
+    ),
+    ("tag_count", "integer", "Number of tags in the file"),
+    (
+        "tag_bytes",
+        "integer",
+        "Total size of the tag blocks, pictures included (estimated when unknown)",
+    ),
+    (
+        "art",
+        "array",
+        "Embedded pictures: type (e.g. front_cover), media type, dimensions, size",
+    ),
+    (
+        "warnings",
+        "array",
+        "Soft problems that didn't stop the file being read",
+    ),
+];
+
+/// `--fields` selectors that pick tags rather than a field.
+const TAG_SELECTORS: &[(&str, &str, &str)] = &[
+    ("tags", "string", "One column per tag key seen in the batch"),
+    (
+        "tag:<key>",
+        "string",
+        "The given tag's value(s), joined with \"; \"",
+    ),
+];
+
+fn is_field(name: &str) -> bool {
+    FIELDS.iter().any(|(field, _, _)| *field == name)
+}
+
+/// Whether `--fields` accepts `name`.
+fn is_selector(name: &str) -> bool {
+    is_field(name) || name == "tags" || name.starts_with("tag:")
+}
+
+fn list_fields() {
+    let rows: Vec<_> = FIELDS.iter().chain(TAG_SELECTORS).collect();
+    let name_width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let type_width = rows
+        .iter()
+        .map(|(_, kind, _)| kind.len())
+        .max()
+        .unwrap_or(0);
+    for (name, kind, about) in rows {
+        println!("{name:<name_width$}  {kind:<type_width$}  {about}");
+    }
+}
+
+/// Table and CSV columns shown when `--fields` isn't given.
+const DEFAULT_COLUMNS: &[&str] = &[
+    "file_path",
+    "codec",
+    "sample_rate",
+    "channels",
+    "duration_seconds",
+    "avg_bitrate_kbps",
+    "file_size_bytes",
+];
+
+enum Column {
+    Field(String),
+    Tag(String),
+}
+
+/// Resolve `--fields`, `--tag` and (with merge_tags) every tag key in the
+/// batch into table/CSV columns.
+fn columns(infos: &[AudioInfo], cli: &Cli, merge_tags: bool) -> Vec<Column> {
+    let mut merge_tags = merge_tags;
+    let mut columns: Vec<Column> = if cli.fields.is_empty() {
+        DEFAULT_COLUMNS
+            .iter()
+            .map(|f| Column::Field(f.to_string()))
+            .collect()
+    } else {
+        let mut columns = Vec::new();
+        for field in &cli.fields {
+            if field == "tags" {
+                merge_tags = true;
+            } else if let Some(key) = field.strip_prefix("tag:") {
+                columns.push(Column::Tag(key.to_string()));
+            } else {
+                columns.push(Column::Field(field.clone()));
+            }
+        }
+        columns
+    };
+
+    let merged = infos.iter().flat_map(|info| &info.tags).map(|(key, _)| key);
+    for key in cli.tag.iter().chain(merged.filter(|_| merge_tags)) {
+        if !columns
+            .iter()
+            .any(|c| matches!(c, Column::Tag(k) if k.eq_ignore_ascii_case(key)))
+        {
+            columns.push(Column::Tag(key.clone()));
+        }
+    }
+    columns
+}
+
+fn column_name(column: &Column, key_case: KeyCase) -> String {
+    match column {
+        Column::Field(name) => name.clone(),
+        // Don't let a tag shadow one of the fields
+        Column::Tag(key) if is_field(key) => format!("tag:{}", key_case.apply(key)),
+        Column::Tag(key) => key_case.apply(key),
+    }
+}
+
+/// The header and rows of a table/CSV listing, cells not yet escaped.
+fn column_cells(
+    infos: &[AudioInfo],
+    columns: &[Column],
+    key_case: KeyCase,
+) -> (Vec<String>, Vec<Vec<String>>) {
+    let header = columns
+        .iter()
+        .map(|column| column_name(column, key_case))
+        .collect();
+
+    let rows = infos
+        .iter()
+        .map(|info| {
+            let object = info_json(info, key_case);
+            columns
+                .iter()
+                .map(|column| match column {
+                    Column::Field(name) => match &object[name.as_str()] {
+                        serde_json::Value::Null => String::new(),
+                        serde_json::Value::String(s) => s.clone(),
+                        serde_json::Value::Array(values) => values
+                            .iter()
+                            .map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string))
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        other => other.to_string(),
+                    },
+                    Column::Tag(key) => {
+                        let values: Vec<&str> = info
+                            .tags
+                            .iter()
+                            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+                            .map(|(_, v)| v.as_str())
+                            .collect();
+                        values.join("; ")
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    (header, rows)
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break.
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn output_csv(
+    out: &mut dyn Write,
+    infos: &[AudioInfo],
+    columns: &[Column],
+    key_case: KeyCase,
+    with_header: bool,
+) -> io::Result<()> {
+    let (header, rows) = column_cells(infos, columns, key_case);
+
+    for row in with_header.then_some(header).into_iter().chain(rows) {
+        let row: Vec<String> = row.iter().map(|cell| csv_escape(cell)).collect();
+        writeln!(out, "{}", row.join(","))?;
+    }
+
+    Ok(())
+}
+
+/// Refuse to append CSV rows under a header with other columns, which would
+/// put values under the wrong names (the tag columns follow the files' tags
+/// unless `--fields` pins them).
+fn check_appended_header(cli: &Cli, columns: &[Column], key_case: KeyCase) -> io::Result<()> {
+    let Some(path) = cli.output.as_deref() else {
+        return Ok(());
+    };
+    let mut existing = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut existing)?;
+    let header: Vec<String> = columns
+        .iter()
+        .map(|column| csv_escape(&column_name(column, key_case)))
+        .collect();
+    if existing.trim_end_matches(['\r', '\n']) == header.join(",") {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the CSV columns differ from the header in {}; pin them with --fields",
+                path.display()
+            ),
+        ))
+    }
+}
+
+/// Cut a cell down to `max` characters, marking the cut with an ellipsis.
+fn truncate(cell: &str, max: Option<usize>) -> String {
+    match max {
+        Some(max) if cell.chars().count() > max => {
+            let mut cut: String = cell.chars().take(max.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+        _ => cell.to_string(),
+    }
+}
+
+/// Make a cell safe inside a Markdown table row: pipes are escaped and line
+/// breaks become `<br>`.
+fn md_escape(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace(['\n', '\r'], "<br>")
+}
+
+fn output_md(
+    out: &mut dyn Write,
+    infos: &[AudioInfo],
+    columns: &[Column],
+    max_width: Option<usize>,
+    key_case: KeyCase,
+) -> io::Result<()> {
+    let (header, rows) = column_cells(infos, columns, key_case);
+    let row_line = |row: &[String]| {
+        let cells: Vec<String> = row
+            .iter()
+            .map(|cell| md_escape(&truncate(cell, max_width)))
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    writeln!(out, "{}", row_line(&header))?;
+    writeln!(out, "|{}", " --- |".repeat(header.len()))?;
+    for row in &rows {
+        writeln!(out, "{}", row_line(row))?;
+    }
+
+    Ok(())
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The style and sorting script of an HTML report, kept inline so the page
+/// works on its own.
+const HTML_STYLE: &str = "\
+body { font: 14px system-ui, sans-serif; margin: 1.5em; color: #222; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f3f3f3; cursor: pointer; user-select: none; white-space: nowrap; }
+th[data-order=asc]::after { content: ' \\25b2'; }
+th[data-order=desc]::after { content: ' \\25bc'; }
+tr:nth-child(even) td { background: #fafafa; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+td { white-space: pre-wrap; }
+img { max-width: 64px; max-height: 64px; }";
+
+const HTML_SCRIPT: &str = "\
+document.querySelectorAll('th').forEach((th, column) => th.addEventListener('click', () => {
+  const body = th.closest('table').tBodies[0];
+  const ascending = th.dataset.order !== 'asc';
+  th.parentElement.querySelectorAll('th').forEach(other => delete other.dataset.order);
+  th.dataset.order = ascending ? 'asc' : 'desc';
+  const value = row => row.cells[column].textContent;
+  const rows = [...body.rows].sort((a, b) => {
+    const [x, y] = [value(a), value(b)];
+    const order = x !== '' && y !== '' && !isNaN(x) && !isNaN(y)
+      ? x - y
+      : x.localeCompare(y, undefined, { numeric: true });
+    return ascending ? order : -order;
+  });
+  body.append(...rows);
+}));";
+
+/// A standalone HTML page with the same columns as the table formats, and
+/// with `--embed-art` a thumbnail column first.
+fn output_html(
+    out: &mut dyn Write,
+    infos: &[AudioInfo],
+    columns: &[Column],
+    cli: &Cli,
+) -> io::Result<()> {
+    let (header, rows) = column_cells(infos, columns, cli.key_case());
+    let numeric: Vec<bool> = columns
+        .iter()
+        .map(|column| {
+            matches!(column, Column::Field(name)
+                if FIELDS.iter().any(|(field, kind, _)| field == name && matches!(*kind, "integer" | "number")))
+        })
+        .collect();
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(
+        out,
+        "<title>audiometa report</title>\n<style>\n{HTML_STYLE}\n</style>\n</head>\n<body>"
+    )?;
+    let files = if infos.len() == 1 { "file" } else { "files" };
+    writeln!(
+        out,
+        "<h1>audiometa report</h1>\n<p>{} {files}</p>",
+        infos.len()
+    )?;
+    writeln!(out, "<table>\n<thead>\n<tr>")?;
+    if cli.embed_art {
+        writeln!(out, "<th>art</th>")?;
+    }
+    for name in &header {
+        writeln!(out, "<th>{}</th>", html_escape(name))?;
+    }
+    writeln!(out, "</tr>\n</thead>\n<tbody>")?;
+    for (info, row) in infos.iter().zip(&rows) {
+        writeln!(out, "<tr>")?;
+        if cli.embed_art {
+            match art_data_uri(info, cli.art_max_dim, cli.quiet) {
+                Some(uri) => {
+                    writeln!(out, "<td><img src=\"{}\" alt=\"\"></td>", html_escape(&uri))?
+                }
+                None => writeln!(out, "<td></td>")?,
+            }
+        }
+        for (cell, &numeric) in row.iter().zip(&numeric) {
+            let class = if numeric { " class=\"num\"" } else { "" };
+            writeln!(out, "<td{class}>{}</td>", html_escape(cell))?;
+        }
+        writeln!(out, "</tr>")?;
+    }
+    writeln!(
+        out,
+        "</tbody>\n</table>\n<script>\n{HTML_SCRIPT}\n</script>\n</body>\n</html>"
+    )
+}
+
+fn output_table(
+    out: &mut dyn Write,
+    infos: &[AudioInfo],
+    columns: &[Column],
+    max_width: Option<usize>,
+    key_case: KeyCase,
+) -> io::Result<()> {
+    let (header, rows) = column_cells(infos, columns, key_case);
+    let clean = |row: Vec<String>| -> Vec<String> {
+        // Line breaks inside a value would break the alignment
+        row.iter()
+            .map(|cell| truncate(&cell.replace(['\n', '\r'], " "), max_width))
+            .collect()
+    };
+    let header = clean(header);
+    let rows: Vec<Vec<String>> = rows.into_iter().map(clean).collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            std::iter::once(&header)
+                .chain(&rows)
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+
+    for row in [header, rule].into_iter().chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+
+    Ok(())
+}
+
+/// Short template names for the most common tags.
+const TEMPLATE_ALIASES: &[(&str, &str)] = &[
+    ("title", "track_title"),
+    ("track", "track_number"),
+    ("disc", "disc_number"),
+];
+
+/// Look up a template name as a field, then a tag key, then a tag alias.
+fn template_value(info: &AudioInfo, object: &serde_json::Value, name: &str) -> Option<String> {
+    if is_field(name) {
+        return match &object[name] {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        };
+    }
+
+    let tag = |key: &str| {
+        info.tags
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            // RIFF INFO values often keep their NUL padding
+            .map(|(_, v)| {
+                v.trim_matches(|c: char| c.is_whitespace() || c == '\0')
+                    .to_string()
+            })
+    };
+    tag(name).or_else(|| {
+        let (_, key) = TEMPLATE_ALIASES
+            .iter()
+            .find(|(alias, _)| name.eq_ignore_ascii_case(alias))?;
+        tag(key)
+    })
+}
+
+/// Check each result against the `verify --expect` conditions, reporting
+/// every field that differs, and return how many files failed.
+fn verify_results(infos: &[AudioInfo], expect: &[(String, String)], quiet: bool) -> usize {
+    let mut failed = 0;
+    for info in infos {
+        let object = info_json(info, KeyCase::Snake);
+        let mut ok = true;
+        for (field, expected) in expect {
+            let actual = template_value(info, &object, field);
+            // Numbers compare by value, so 48000 matches 48000.0
+            let matches = actual.as_deref().is_some_and(|actual| {
+                actual == expected
+                    || matches!((actual.parse::<f64>(), expected.parse::<f64>()), (Ok(a), Ok(e)) if a == e)
+            });
+            if !matches {
+                ok = false;
+                if !quiet {
+                    let actual = actual
+                        .map_or_else(|| "missing".to_string(), |actual| format!("'{actual}'"));
+                    report!(
+                        "Mismatch in {}: {field} is {actual}, expected '{expected}'",
+                        info.file_path
+                    );
+                }
+            }
+        }
+        if !ok {
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// A file's `--group-by` value, made safe to use as a file name.
+fn group_key(info: &AudioInfo, field: &str) -> String {
+    let object = info_json(info, KeyCase::Snake);
+    template_value(info, &object, field)
+        .map(|value| sanitize_file_name(&value))
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Reorder the results so files with the same `--group-by` value sit
+/// together, groups in the order they first appear, and return each group's
+/// value and range.
+fn group_results(
+    inputs: &mut Vec<&Input>,
+    infos: &mut Vec<AudioInfo>,
+    field: &str,
+) -> Vec<(String, Range<usize>)> {
+    let keys: Vec<String> = infos.iter().map(|info| group_key(info, field)).collect();
+    let mut names: Vec<&String> = Vec::new();
+    for key in &keys {
+        if !names.contains(&key) {
+            names.push(key);
+        }
+    }
+    let ranks: Vec<usize> = keys
+        .iter()
+        .map(|key| names.iter().position(|n| *n == key).unwrap())
+        .collect();
+
+    let mut rows: Vec<_> = ranks
+        .into_iter()
+        .zip(inputs.drain(..).zip(infos.drain(..)))
+        .collect();
+    // Stable, so files keep their order within a group
+    rows.sort_by_key(|(rank, _)| *rank);
+
+    let mut groups: Vec<(String, Range<usize>)> = Vec::new();
+    for (i, (rank, (input, info))) in rows.into_iter().enumerate() {
+        match groups.last_mut() {
+            Some((name, range)) if name == names[rank] => range.end = i + 1,
+            _ => groups.push((names[rank].clone(), i..i + 1)),
+        }
+        inputs.push(input);
+        infos.push(info);
+    }
+    groups
+}
+
+/// Write each group's report to `<dir>/<value>.<ext>`, returning how many
+/// couldn't be written.
+fn write_group_reports(
+    dir: &Path,
+    infos: &[AudioInfo],
+    groups: &[(String, Range<usize>)],
+    format: &OutputFormat,
+    cli: &Cli,
+) -> usize {
+    let mut problems = 0;
+
+    for (name, range) in groups {
+        let path = dir.join(format!("{name}.{}", format.extension()));
+        let result = fs::create_dir_all(dir)
+            .and_then(|_| File::create(&path))
+            .and_then(|file| {
+                let mut out = BufWriter::new(file);
+                output_report(&mut out, &infos[range.clone()], format, cli)?;
+                out.flush()
+            });
+
+        if let Err(e) = result {
+            if !cli.quiet {
+                report!("Error writing {}: {e}", path.display());
+            }
+            problems += 1;
+        }
+    }
+
+    problems
+}
+
+/// Fill `{name}` and `{name:0N}` placeholders from a file's fields and tags.
+/// `{{` and `}}` stand for literal braces.
+fn render_template(template: &str, info: &AudioInfo) -> Result<String, String> {
+    let object = info_json(info, KeyCase::Snake);
+    let mut rendered = String::new();
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or("unclosed '{' in template")?;
+                let (name, spec) = rest[..end].split_once(':').unwrap_or((&rest[..end], ""));
+                chars = rest[end + 1..].chars();
+
+                let value = template_value(info, &object, name)
+                    .filter(|v| !v.is_empty())
+                    .ok_or_else(|| format!("no '{name}' to fill the template"))?;
+                if spec.is_empty() {
+                    rendered.push_str(&value);
+                    continue;
+                }
+
+                let width: usize = spec
+                    .parse()
+                    .map_err(|_| format!("invalid width '{spec}' in template"))?;
+                // Track numbers are often stored as "3/12"
+                let number = value.split('/').next().unwrap_or(&value).trim();
+                match number.parse::<u64>() {
+                    Ok(n) if spec.starts_with('0') => rendered.push_str(&format!("{n:0width$}")),
+                    Ok(n) => rendered.push_str(&format!("{n:width$}")),
+                    Err(_) => rendered.push_str(&value),
+                }
+            }
+            c => rendered.push(c),
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Replace characters that aren't allowed in file names on common filesystems.
+fn sanitize_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces
+    cleaned.trim().trim_end_matches(['.', ' ']).to_string()
+}
+
+/// Print the suggested name for each file and, with `rename`, apply the
+/// suggestions. Returns how many files couldn't be renamed.
+fn suggest_renames(
+    out: &mut dyn Write,
+    inputs: &[&Input],
+    infos: &[AudioInfo],
+    template: &str,
+    rename: bool,
+    quiet: bool,
+) -> io::Result<usize> {
+    let mut problems = 0;
+    let mut plans: Vec<(&AudioInfo, &Path, Option<PathBuf>)> = Vec::new();
+
+    for (input, info) in inputs.iter().zip(infos) {
+        let Input::File(path) = input else {
+            if !quiet {
+                report!("Error with {input}: only local files can be renamed");
+            }
+            problems += 1;
+            continue;
+        };
+        let target = match render_template(template, info) {
+            Ok(name) => {
+                let mut name = sanitize_file_name(&name);
+                if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+                    name = format!("{name}.{ext}");
+                }
+                Some(path.with_file_name(name))
+            }
+            Err(e) => {
+                if !quiet {
+                    report!("Error with {input}: {e}");
+                }
+                problems += 1;
+                None
+            }
+        };
+        plans.push((info, path, target));
+    }
+
+    for (info, path, target) in &plans {
+        let Some(target) = target else {
+            continue;
+        };
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        if target == path {
+            writeln!(out, "{}: unchanged", info.file_path)?;
+            continue;
+        }
+
+        let clashes = plans
+            .iter()
+            .filter(|(_, _, t)| t.as_ref() == Some(target))
+            .count()
+            > 1;
+        if clashes || target.exists() {
+            writeln!(out, "{} -> {name} (collision, skipped)", info.file_path)?;
+            problems += 1;
+            continue;
+        }
+
+        writeln!(out, "{} -> {name}", info.file_path)?;
+        if rename && let Err(e) = fs::rename(path, target) {
+            if !quiet {
+                report!("Error renaming {}: {e}", info.file_path);
+            }
+            problems += 1;
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Write the lyrics of each file that has them, headed by its path when
+/// there is more than one file.
+fn dump_lyrics(out: &mut dyn Write, infos: &[AudioInfo], quiet: bool) -> io::Result<()> {
+    let mut first = true;
+    for info in infos {
+        let Some(lyrics) = info.lyrics.as_ref().or(info.synced_lyrics.as_ref()) else {
+            if !quiet {
+                report!("Warning: No lyrics in {}", info.file_path);
+            }
+            continue;
+        };
+
+        if infos.len() > 1 {
+            if !first {
+                writeln!(out)?;
+            }
+            writeln!(out, "==> {} <==", info.file_path)?;
+        }
+        first = false;
+        writeln!(out, "{}", lyrics.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Write each file's tags as `key=value` lines, headed by its path when there
+/// is more than one file. Backslashes and line breaks in values are escaped so
+/// every tag stays on one line.
+fn output_tags(out: &mut dyn Write, infos: &[AudioInfo], cli: &Cli) -> io::Result<()> {
+    let key_case = cli.key_case();
+    for (i, info) in infos.iter().enumerate() {
+        if infos.len() > 1 {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "==> {} <==", info.file_path)?;
+        }
+        let selected = info.tags.iter().filter(|(key, _)| {
+            cli.tag.is_empty() || cli.tag.iter().any(|k| k.eq_ignore_ascii_case(key))
+        });
+        for (key, value) in selected {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            writeln!(out, "{}={value}", key_case.apply(key))?;
+        }
+    }
+    Ok(())
+}
+
+/// What the tracks of one folder have in common, for `--album-check`.
+struct AlbumCheck {
+    folder: String,
+    tracks: usize,
+    /// Distinct values, in the order first seen
+    sample_rates: Vec<Option<u32>>,
+    bit_depths: Vec<Option<u32>>,
+    codecs: Vec<Option<String>>,
+}
+
+impl AlbumCheck {
+    /// The properties whose values differ between tracks.
+    fn mixed(&self) -> Vec<&'static str> {
+        [
+            ("sample_rate", self.sample_rates.len()),
+            ("bits_per_sample", self.bit_depths.len()),
+            ("codec", self.codecs.len()),
+        ]
+        .into_iter()
+        .filter(|(_, distinct)| *distinct > 1)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// The folder holding `input`: its parent directory, the directory inside an
+/// archive, or a URL up to its last `/`.
+fn input_folder(input: &Input) -> String {
+    match input {
+        Input::File(path) => match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.display().to_string(),
+            _ => ".".to_string(),
+        },
+        Input::Url(url) => url
+            .rsplit_once('/')
+            .map_or(url.as_str(), |(folder, _)| folder)
+            .to_string(),
+        Input::Archived(entry) => match Path::new(&entry.name).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                format!("{}!{}", entry.archive.display(), parent.display())
+            }
+            _ => entry.archive.display().to_string(),
+        },
+    }
+}
+
+/// Group the results by folder, in the order folders first appear.
+fn album_checks(inputs: &[&Input], infos: &[AudioInfo]) -> Vec<AlbumCheck> {
+    fn add<T: PartialEq>(values: &mut Vec<T>, value: T) {
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    let mut checks: Vec<AlbumCheck> = Vec::new();
+    for (input, info) in inputs.iter().zip(infos) {
+        let folder = input_folder(input);
+        let index = match checks.iter().position(|check| check.folder == folder) {
+            Some(index) => index,
+            None => {
+                checks.push(AlbumCheck {
+                    folder,
+                    tracks: 0,
+                    sample_rates: Vec::new(),
+                    bit_depths: Vec::new(),
+                    codecs: Vec::new(),
+                });
+                checks.len() - 1
+            }
+        };
+        let check = &mut checks[index];
+        check.tracks += 1;
+        add(&mut check.sample_rates, info.sample_rate);
+        add(&mut check.bit_depths, info.bits_per_sample);
+        add(&mut check.codecs, info.codec.clone());
+    }
+    checks
+}
+
+/// How many of `infos` have each value of `field` (a field or tag), most
+/// common first and ties in the order they first appear. Files without one
+/// count as `unknown`.
+fn count_values(infos: &[AudioInfo], field: &str) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for info in infos {
+        let object = info_json(info, KeyCase::Snake);
+        let value = template_value(info, &object, field)
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "unknown".to_string());
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+    // Stable, so equal counts keep their first-seen order
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts
+}
+
+fn output_counts(
+    out: &mut dyn Write,
+    counts: &[(String, usize)],
+    format: &OutputFormat,
+    sep: &str,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let object: serde_json::Map<_, _> = counts
+                .iter()
+                .map(|(value, count)| (value.clone(), json!(count)))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&object).unwrap())
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "value,count")?;
+            for (value, count) in counts {
+                writeln!(out, "{},{count}", csv_escape(value))?;
+            }
+            Ok(())
+        }
+        _ => {
+            for (value, count) in counts {
+                writeln!(out, "{value}{sep}{count}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// How many of `infos` fall into each `bin_size`-second duration bin, from
+/// the first bin up to the longest file's, empty bins included, and how many
+/// have no known duration.
+fn duration_bins(infos: &[AudioInfo], bin_size: u64) -> (Vec<usize>, usize) {
+    let mut bins = Vec::new();
+    let mut unknown = 0;
+    for info in infos {
+        let Some(duration) = info.duration_seconds else {
+            unknown += 1;
+            continue;
+        };
+        let bin = (duration / bin_size) as usize;
+        if bin >= bins.len() {
+            bins.resize(bin + 1, 0);
+        }
+        bins[bin] += 1;
+    }
+    (bins, unknown)
+}
+
+/// The widest `--duration-histogram` bar, in characters.
+const HISTOGRAM_WIDTH: usize = 40;
+
+fn output_histogram(
+    out: &mut dyn Write,
+    (bins, unknown): &(Vec<usize>, usize),
+    bin_size: u64,
+    format: &OutputFormat,
+) -> io::Result<()> {
+    fn clock(seconds: u64) -> String {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+
+    let start = |bin: usize| bin as u64 * bin_size;
+    match format {
+        OutputFormat::Json => {
+            let records: Vec<_> = bins
+                .iter()
+                .enumerate()
+                .map(|(bin, count)| {
+                    json!({ "start_seconds": start(bin), "end_seconds": start(bin + 1), "count": count })
+                })
+                .collect();
+            let histogram =
+                json!({ "bin_size_seconds": bin_size, "bins": records, "unknown": unknown });
+            writeln!(out, "{}", serde_json::to_string_pretty(&histogram).unwrap())
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "start_seconds,end_seconds,count")?;
+            for (bin, count) in bins.iter().enumerate() {
+                writeln!(out, "{},{},{count}", start(bin), start(bin + 1))?;
+            }
+            Ok(())
+        }
+        _ => {
+            let labels: Vec<String> = (0..bins.len())
+                .map(|bin| format!("{}-{}", clock(start(bin)), clock(start(bin + 1))))
+                .collect();
+            let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+            let most = bins.iter().copied().max().unwrap_or(0).max(1);
+            for (label, &count) in labels.iter().zip(bins) {
+                // Any file at all gets at least one mark
+                let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(most));
+                writeln!(
+                    out,
+                    "{label:>label_width$} | {bar:<HISTOGRAM_WIDTH$} {count}"
+                )?;
+            }
+            if *unknown > 0 {
+                writeln!(out, "unknown duration: {unknown}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn output_album_check(
+    out: &mut dyn Write,
+    checks: &[AlbumCheck],
+    format: &OutputFormat,
+) -> io::Result<()> {
+    fn list<T: ToString>(values: &[Option<T>]) -> Vec<String> {
+        values
+            .iter()
+            .map(|v| {
+                v.as_ref()
+                    .map_or_else(|| "unknown".to_string(), T::to_string)
+            })
+            .collect()
+    }
+
+    match format {
+        OutputFormat::Json => {
+            let records: Vec<_> = checks
+                .iter()
+                .map(|check| {
+                    json!({
+                        "folder": check.folder,
+                        "tracks": check.tracks,
+                        "consistent": check.mixed().is_empty(),
+                        "mixed": check.mixed(),
+                        "sample_rates": check.sample_rates,
+                        "bits_per_sample": check.bit_depths,
+                        "codecs": check.codecs,
+                    })
+                })
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&records).unwrap())
+        }
+        OutputFormat::Csv => {
+            writeln!(
+                out,
+                "folder,tracks,consistent,mixed,sample_rates,bits_per_sample,codecs"
+            )?;
+            for check in checks {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv_escape(&check.folder),
+                    check.tracks,
+                    check.mixed().is_empty(),
+                    csv_escape(&check.mixed().join("; ")),
+                    csv_escape(&list(&check.sample_rates).join("; ")),
+                    csv_escape(&list(&check.bit_depths).join("; ")),
+                    csv_escape(&list(&check.codecs).join("; ")),
+                )?;
+            }
+            Ok(())
+        }
+        _ => {
+            for (i, check) in checks.iter().enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                let mixed = check.mixed();
+                writeln!(out, "folder: {}", check.folder)?;
+                writeln!(out, "tracks: {}", check.tracks)?;
+                writeln!(out, "consistent: {}", mixed.is_empty())?;
+                let properties = [
+                    ("sample_rate", list(&check.sample_rates)),
+                    ("bits_per_sample", list(&check.bit_depths)),
+                    ("codec", list(&check.codecs)),
+                ];
+                for (name, values) in properties {
+                    let note = if mixed.contains(&name) {
+                        " (MIXED)"
+                    } else {
+                        ""
+                    };
+                    writeln!(out, "{name}: {}{note}", values.join(", "))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// List files that failed to parse, for `--unknown-only`.
+fn output_failures(
+    out: &mut dyn Write,
+    failures: &[(String, String)],
+    format: &OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let records: Vec<_> = failures
+                .iter()
+                .map(|(path, error)| json!({ "file_path": path, "error": error }))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&records).unwrap())
+        }
+        OutputFormat::Ndjson => {
+            for (path, error) in failures {
+                writeln!(out, "{}", json!({ "file_path": path, "error": error }))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "file_path,error")?;
+            for (path, error) in failures {
+                writeln!(out, "{},{}", csv_escape(path), csv_escape(error))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Md => {
+            writeln!(out, "| file_path | error |")?;
+            writeln!(out, "| --- | --- |")?;
+            for (path, error) in failures {
+                writeln!(out, "| {} | {} |", md_escape(path), md_escape(error))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Parquet => Err(parquet_needs_file()),
+        OutputFormat::Auto | OutputFormat::Text | OutputFormat::Table | OutputFormat::Html => {
+            for (path, error) in failures {
+                writeln!(out, "{path}: {error}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Write `infos` in an already resolved `format`.
+fn output_report(
+    out: &mut dyn Write,
+    infos: &[AudioInfo],
+    format: &OutputFormat,
+    cli: &Cli,
+) -> io::Result<()> {
+    let key_case = cli.key_case();
+    match format {
+        OutputFormat::Auto | OutputFormat::Text => output_text(out, infos, cli),
+        OutputFormat::Json => output_json(out, infos, cli),
+        OutputFormat::Ndjson => {
+            for info in infos {
+                writeln!(out, "{}", record_json(info, cli))?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv => {
+            let columns = columns(infos, cli, cli.merge_csv);
+            let appending = appending_to_data(cli);
+            if appending {
+                check_appended_header(cli, &columns, key_case)?;
+            }
+            output_csv(out, infos, &columns, key_case, !appending)
+        }
+        OutputFormat::Table => {
+            let columns = columns(infos, cli, false);
+            output_table(out, infos, &columns, cli.max_width, key_case)
+        }
+        OutputFormat::Md => {
+            let columns = columns(infos, cli, false);
+            output_md(out, infos, &columns, cli.max_width, key_case)
+        }
+        OutputFormat::Html => {
+            let columns = columns(infos, cli, false);
+            output_html(out, infos, &columns, cli)
+        }
+        OutputFormat::Parquet => Err(parquet_needs_file()),
+    }
+}
+
+fn parquet_needs_file() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Parquet output can only be written to an --output file",
+    )
+}
+
+/// Rows per Parquet row group, so a large batch is written a piece at a time.
+#[cfg(feature = "parquet")]
+const PARQUET_ROW_GROUP: usize = 1024;
+
+/// Write `infos` to a Parquet file at `path`. Without `--fields` that is every
+/// scalar field plus the tags as a `tags` map column; with `--fields`, `tags`
+/// selects the map column and `tag:<key>` a column of its own.
+#[cfg(feature = "parquet")]
+fn output_parquet(path: &Path, infos: &[AudioInfo], cli: &Cli) -> io::Result<()> {
+    use arrow_array::{
+        ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch,
+        builder::{MapBuilder, StringBuilder},
+    };
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+    use std::sync::Arc;
+
+    let mut tag_map = cli.fields.is_empty();
+    let mut columns: Vec<Column> = if cli.fields.is_empty() {
+        FIELDS
+            .iter()
+            .filter(|(_, kind, _)| *kind != "array")
+            .map(|(name, _, _)| Column::Field(name.to_string()))
+            .collect()
+    } else {
+        let mut columns = Vec::new();
+        for field in &cli.fields {
+            if field == "tags" {
+                tag_map = true;
+            } else if let Some(key) = field.strip_prefix("tag:") {
+                columns.push(Column::Tag(key.to_string()));
+            } else {
+                columns.push(Column::Field(field.clone()));
+            }
+        }
+        columns
+    };
+    for key in &cli.tag {
+        if !columns
+            .iter()
+            .any(|c| matches!(c, Column::Tag(k) if k.eq_ignore_ascii_case(key)))
+        {
+            columns.push(Column::Tag(key.clone()));
+        }
+    }
+
+    let key_case = cli.key_case();
+    let batch = |infos: &[AudioInfo]| -> Result<RecordBatch, arrow_schema::ArrowError> {
+        let objects: Vec<_> = infos.iter().map(|info| info_json(info, key_case)).collect();
+        let mut arrays: Vec<(String, ArrayRef)> = Vec::new();
+        for column in &columns {
+            let array: ArrayRef = match column {
+                Column::Field(name) => {
+                    let kind = FIELDS
+                        .iter()
+                        .find(|(field, _, _)| field == name)
+                        .map_or("string", |(_, kind, _)| *kind);
+                    let values = objects.iter().map(|object| &object[name.as_str()]);
+                    match kind {
+                        "integer" => Arc::new(values.map(|v| v.as_i64()).collect::<Int64Array>()),
+                        "number" => Arc::new(values.map(|v| v.as_f64()).collect::<Float64Array>()),
+                        "boolean" => {
+                            Arc::new(values.map(|v| v.as_bool()).collect::<BooleanArray>())
+                        }
+                        _ => {
+                            let mut builder = StringBuilder::new();
+                            for value in values {
+                                match value {
+                                    serde_json::Value::Null => builder.append_null(),
+                                    serde_json::Value::String(s) => builder.append_value(s),
+                                    // Arrays and objects go in as their JSON text
+                                    other => builder.append_value(other.to_string()),
+                                }
+                            }
+                            Arc::new(builder.finish())
+                        }
+                    }
+                }
+                Column::Tag(key) => {
+                    let mut builder = StringBuilder::new();
+                    for info in infos {
+                        let values: Vec<&str> = info
+                            .tags
+                            .iter()
+                            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+                            .map(|(_, v)| v.as_str())
+                            .collect();
+                        builder.append_option((!values.is_empty()).then(|| values.join("; ")));
+                    }
+                    Arc::new(builder.finish())
+                }
+            };
+            arrays.push((column_name(column, key_case), array));
+        }
+        if tag_map {
+            let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+            for info in infos {
+                for (key, value) in &info.tags {
+                    builder.keys().append_value(key_case.apply(key));
+                    builder.values().append_value(value);
+                }
+                builder.append(true)?;
+            }
+            arrays.push(("tags".to_string(), Arc::new(builder.finish())));
+        }
+        RecordBatch::try_from_iter(arrays)
+    };
+
+    let mut chunks = infos.chunks(PARQUET_ROW_GROUP);
+    // An empty run still gets a file, with just the schema
+    let first = batch(chunks.next().unwrap_or(infos)).map_err(io::Error::other)?;
+    let properties = WriterProperties::builder()
+        .set_max_row_group_row_count(Some(PARQUET_ROW_GROUP))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, first.schema(), Some(properties))
+        .map_err(io::Error::other)?;
+    writer.write(&first).map_err(io::Error::other)?;
+    for chunk in chunks {
+        writer
+            .write(&batch(chunk).map_err(io::Error::other)?)
+            .map_err(io::Error::other)?;
+    }
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn output_parquet(_path: &Path, _infos: &[AudioInfo], _cli: &Cli) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "this build has no Parquet support",
+    ))
+}
+
+/// Where `--output-dir` puts the report for `input`: its stem plus the
+/// format's extension, under the same subdirectories with `--preserve-tree`.
+fn report_path(dir: &Path, input: &Input, cli: &Cli, ext: &str) -> PathBuf {
+    let (subdir, stem) = match input {
+        Input::File(path) => {
+            let subdir = cli
+                .preserve_tree
+                .then(|| {
+                    let parent = path.parent()?;
+                    cli.files
+                        .iter()
+                        .filter(|root| root.is_dir())
+                        .find_map(|root| parent.strip_prefix(root).ok())
+                })
+                .flatten();
+            (
+                subdir,
+                path.file_stem().map(|s| s.to_string_lossy().into_owned()),
+            )
+        }
+        Input::Url(url) => {
+            let name = url
+                .split(['?', '#'])
+                .next()
+                .unwrap_or(url)
+                .rsplit('/')
+                .next();
+            let stem = name.map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem));
+            (None, stem.filter(|s| !s.is_empty()).map(str::to_string))
+        }
+        Input::Archived(entry) => {
+            let name = Path::new(&entry.name);
+            let subdir = cli.preserve_tree.then(|| name.parent()).flatten();
+            (
+                subdir,
+                name.file_stem().map(|s| s.to_string_lossy().into_owned()),
+            )
+        }
+    };
+
+    let mut path = dir.to_path_buf();
+    if let Some(subdir) = subdir {
+        // Tar entry names aren't sanitized, and a root or `..` would put the
+        // report outside `dir`
+        path.extend(
+            subdir
+                .components()
+                .filter(|part| matches!(part, Component::Normal(_))),
+        );
+    }
+    path.push(format!("{}.{ext}", stem.as_deref().unwrap_or("report")));
+    path
+}
+
+/// Write each file's report into `dir`. Returns how many couldn't be written,
+/// including inputs whose report name was already taken in this run.
+fn write_reports(
+    dir: &Path,
+    inputs: &[&Input],
+    infos: &[AudioInfo],
+    format: &OutputFormat,
+    cli: &Cli,
+) -> usize {
+    let mut written: Vec<PathBuf> = Vec::new();
+    let mut problems = 0;
+
+    for (input, info) in inputs.iter().zip(infos) {
+        let path = report_path(dir, input, cli, format.extension());
+        let result = if written.contains(&path) {
+            Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "another input already wrote this report",
+            ))
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| File::create(&path))
+                .and_then(|file| {
+                    let mut out = BufWriter::new(file);
+                    output_report(&mut out, std::slice::from_ref(info), format, cli)?;
+                    out.flush()
+                })
+        };
+
+        match result {
+            Ok(()) => written.push(path),
+            Err(e) => {
+                if !cli.quiet {
+                    report!("Error writing {} for {input}: {e}", path.display());
+                }
+                problems += 1;
+            }
+        }
+    }
+
+    problems
+}
+
+/// `--art-type` shorthands for the picture types people usually want.
+const ART_TYPE_ALIASES: &[(&str, &str)] = &[
+    ("front", "front_cover"),
+    ("cover", "front_cover"),
+    ("back", "back_cover"),
+    ("artist", "artist_performer"),
+];
+
+/// File extension for an image MIME type.
+fn image_extension(media_type: &str) -> &str {
+    match media_type.to_ascii_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        _ => "bin",
+    }
+}
+
+/// Write the pictures of one file into `dir` as `<stem>.<type>.<ext>`, adding
+/// `-2`, `-3`, ... for repeats of a type. Returns how many couldn't be written.
+fn extract_art(dir: &Path, input: &Input, info: &AudioInfo, cli: &Cli) -> usize {
+    let wanted = cli.art_type.as_deref().map(|kind| {
+        let kind = kind.to_case(Case::Snake);
+        ART_TYPE_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == kind)
+            .map_or(kind, |(_, name)| name.to_string())
+    });
+    let pictures: Vec<_> = info
+        .art
+        .iter()
+        .filter(|art| wanted.is_none() || art.kind == wanted)
+        .collect();
+    if pictures.is_empty() {
+        if !cli.quiet {
+            match &wanted {
+                Some(kind) => report!("Note: No {kind} picture in {input}"),
+                None => report!("Note: No pictures in {input}"),
+            }
+        }
+        return 0;
+    }
+
+    let stem = match input {
+        Input::File(path) => path.file_stem().map(|s| s.to_string_lossy().into_owned()),
+        Input::Archived(entry) => Path::new(&entry.name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned()),
+        Input::Url(_) => None,
+    }
+    .unwrap_or_else(|| info.id.clone());
+
+    if let Err(e) = fs::create_dir_all(dir) {
+        if !cli.quiet {
+            report!("Error: cannot create {}: {e}", dir.display());
+        }
+        return pictures.len();
+    }
+
+    let mut failed = 0;
+    let mut seen: Vec<&str> = Vec::new();
+    for art in pictures {
+        let kind = art.kind.as_deref().unwrap_or("picture");
+        let repeat = seen.iter().filter(|k| **k == kind).count();
+        seen.push(kind);
+        let suffix = if repeat == 0 {
+            String::new()
+        } else {
+            format!("-{}", repeat + 1)
+        };
+        let path = dir.join(format!(
+            "{stem}.{kind}{suffix}.{}",
+            image_extension(&art.media_type)
+        ));
+        if let Err(e) = fs::write(&path, &art.data) {
+            if !cli.quiet {
+                report!("Error writing {} for {input}: {e}", path.display());
+            }
+            failed += 1;
+        }
+    }
+    failed
+}
+
+/// Expand the command-line paths into inputs. With `--input-format`, files
+/// named directly are taken even with an unaccepted (or no) extension, since
+/// the format no longer comes from it.
+fn collect_inputs(
+    exts: &[String],
+    cli: &Cli,
+    mut warn: impl FnMut(AudioMetaError),
+) -> Result<Vec<Input>, AudioMetaError> {
+    if cli.input_format.is_none() {
+        return collect_audio_files(&cli.files, exts, cli.walk(), warn);
+    }
+
+    let mut inputs = Vec::new();
+    for path in &cli.files {
+        if path.is_file() && !is_archive(path) && !is_audio_file(path, exts) {
+            inputs.push(Input::File(path.clone()));
+        } else {
+            inputs.extend(collect_audio_files(
+                std::slice::from_ref(path),
+                exts,
+                cli.walk(),
+                &mut warn,
+            )?);
+        }
+    }
+    Ok(inputs)
+}
+
+/// How often `--follow-file-list` checks the list for new lines.
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+/// Process the paths in `list` as lines are appended to it, writing one
+/// compact JSON record per file and flushing after each. A line is only
+/// taken once its newline has been written; if the list shrinks (truncated
+/// or replaced) it is read again from the start. Failures are reported and
+/// skipped, as under `--keep-going`.
+fn follow_file_list(
+    out: &mut dyn Write,
+    list: &Path,
+    exts: &[String],
+    tag_filter: &TagFilter,
+    cli: &Cli,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(list)?);
+    let mut pos = 0u64;
+    let mut line = String::new();
+    let batch = cli.jsonl_buffer.max(1);
+    let mut pending = 0;
+
+    while !INTERRUPTED.load(Ordering::Relaxed) {
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            // Nothing new to batch with, so don't sit on what's written
+            if pending > 0 {
+                out.flush()?;
+                pending = 0;
+            }
+            // Wait for the rest of the line, restarting if the list was cut short
+            if fs::metadata(list).is_ok_and(|m| m.len() < pos) {
+                reader = BufReader::new(File::open(list)?);
+                pos = 0;
+                line.clear();
+            }
+            thread::sleep(FOLLOW_POLL);
+            continue;
+        }
+        pos += line.len() as u64;
+
+        let warn = |e| {
+            if !cli.quiet {
+                report!("Warning: {e}");
+            }
+        };
+        let inputs = inputs_from_line(&line, exts, cli.walk(), warn).unwrap_or_else(|e| {
+            warn(e);
+            Vec::new()
+        });
+        for input in inputs {
+            match process_input(&input, cli) {
+                Ok(info) if !tag_filter.keep(&info) || !codec_selected(&info, &cli.codec_only) => {}
+                Ok(info) => {
+                    writeln!(out, "{}", record_json(&info, cli))?;
+                    pending += 1;
+                    if pending >= batch {
+                        out.flush()?;
+                        pending = 0;
+                    }
+                }
+                Err(e) => {
+                    if !cli.quiet {
+                        report!("Error with {input}: {e}");
+                    }
+                }
+            }
+        }
+        line.clear();
+    }
+    out.flush()
+}
+
+/// The `--profile` summary: how much was read, how long it took, and the
+/// throughput that gives.
+fn print_profile(files: usize, failed: usize, bytes: u64, elapsed: Duration, jobs: usize) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    let megabytes = bytes as f64 / 1_000_000.0;
+    eprintln!(
+        "profile: {files} files ({failed} failed), {megabytes:.1} MB in {seconds:.3}s with {jobs} job{}",
+        if jobs == 1 { "" } else { "s" }
+    );
+    eprintln!(
+        "profile: {:.1} files/s, {:.1} MB/s",
+        files as f64 / seconds,
+        megabytes / seconds
+    );
+}
+
+fn open_output(cli: &Cli) -> Box<dyn Write> {
+    let mut options = fs::OpenOptions::new();
+    options
+        .write(true)
+        .create(true)
+        .append(cli.append)
+        .truncate(!cli.append);
+    match &cli.output {
+        Some(path) => match options.open(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                if !cli.quiet {
+                    report!("Error: cannot create {}: {e}", path.display());
+                }
+                process::exit(1);
+            }
+        },
+        None if HELD.lock().unwrap_or_else(|e| e.into_inner()).is_some() => Box::new(HeldStdout),
+        None => Box::new(io::stdout().lock()),
+    }
+}
+
+/// Whether `--append` is adding to an `--output` file that already has
+/// something in it, so a CSV header is there already.
+fn appending_to_data(cli: &Cli) -> bool {
+    cli.append
+        && cli
+            .output
+            .as_deref()
+            .is_some_and(|path| fs::metadata(path).is_ok_and(|meta| meta.len() > 0))
+}
+
+/// Output for the streaming modes. Stdout is line-buffered, which would
+/// still write every record, so batching flushes needs a buffer of its own.
+fn open_stream_output(cli: &Cli) -> Box<dyn Write> {
+    let out = open_output(cli);
+    if cli.jsonl_buffer > 1 {
+        Box::new(BufWriter::new(out))
+    } else {
+        out
+    }
+}
+
+/// Exit after a failed write. A closed pipe (e.g. `| head`) just means the
+/// reader has seen enough, so it is not reported as an error.
+fn exit_write_error(e: io::Error, cli: &Cli) -> ! {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        process::exit(0);
+    }
+    release_held();
+    if !cli.quiet {
+        report!("Error: failed to write output: {e}");
+    }
+    process::exit(1);
+}
+
+/// The config file named by `--config`, if any. It has to be found before
+/// clap parses the command line, since it supplies clap's defaults.
+fn config_arg(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str()?;
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Turn a config value into the strings clap would have parsed.
+fn config_values(value: &toml::Value) -> Option<Vec<String>> {
+    match value {
+        toml::Value::String(s) => Some(vec![s.clone()]),
+        toml::Value::Integer(n) => Some(vec![n.to_string()]),
+        toml::Value::Float(n) => Some(vec![n.to_string()]),
+        toml::Value::Boolean(b) => Some(vec![b.to_string()]),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|v| {
+                config_values(v)
+                    .filter(|v| v.len() == 1)
+                    .map(|mut v| v.remove(0))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Make every option set in a config file the default for its flag, so the
+/// command line (and environment) still override it.
+fn apply_config(mut command: clap::Command, path: &Path) -> Result<clap::Command, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    for (key, value) in &table {
+        let id = key.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some())
+            .filter(|_| id != "config")
+            .ok_or_else(|| format!("{}: unknown option '{key}'", path.display()))?;
+        let multiple = matches!(arg.get_action(), ArgAction::Append);
+        let values = config_values(value)
+            .filter(|values| multiple || values.len() == 1)
+            .ok_or_else(|| format!("{}: invalid value for '{key}'", path.display()))?;
+        // The analysis subcommands share the top-level options
+        for name in ["show", "verify"] {
+            command = command.mut_subcommand(name, |sub| {
+                sub.mut_arg(&id, |arg| arg.default_values(values.clone()))
+            });
+        }
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
+
+/// Parse the command line on top of the defaults from `--config`, or from
+/// `.audiometa.toml` in the working directory when it exists.
+fn parse_cli() -> Invocation {
+    let args: Vec<OsString> = env::args_os().collect();
+    let config = config_arg(&args).or_else(|| {
+        let default = PathBuf::from(".audiometa.toml");
+        default.is_file().then_some(default)
+    });
+
+    let mut command = Invocation::command();
+    if let Some(path) = config {
+        command = apply_config(command, &path).unwrap_or_else(|e| {
+            report!("Error: Invalid config {e}");
+            process::exit(1);
+        });
+    }
+
+    let mut matches = command.get_matches_from(args);
+    Invocation::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.exit())
+}
+
+fn main() {
+    let invocation = parse_cli();
+    let (cli, expect) = match invocation.command {
+        None => (invocation.cli, None),
+        Some(Command::Show(cli)) => (*cli, None),
+        Some(Command::Verify { expect, cli }) => (*cli, Some(expect)),
+        Some(Command::Completions { shell }) => {
+            // Generated into memory since clap_complete panics on a failed write
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Invocation::command(), "audiometa", &mut script);
+            if let Err(e) = io::stdout().write_all(&script)
+                && e.kind() != io::ErrorKind::BrokenPipe
+            {
+                exit_write_error(e, &invocation.cli);
+            }
+            return;
+        }
+    };
+
+    if let Some(path) = &cli.errors_to {
+        match File::create(path) {
+            Ok(file) => {
+                let _ = ERROR_LOG.set(Mutex::new(file));
+            }
+            Err(e) => {
+                if !cli.quiet {
+                    report!("Error: cannot create {}: {e}", path.display());
+                }
+                process::exit(1);
+            }
+        }
+    }
+
+    if cli.list_fields {
+        list_fields();
+        return;
+    }
+
+    let use_stdin = cli.files.is_empty() && cli.follow_file_list.is_none();
+
+    if use_stdin && io::stdin().is_terminal() {
+        if !cli.quiet {
+            report!("Error: No files provided and no data available on stdin");
+        }
+        process::exit(1);
+    }
+
+    if let Some(field) = cli.fields.iter().find(|f| !is_selector(f)) {
+        if !cli.quiet {
+            report!("Error: Unknown field '{field}' (see --list-fields)");
+        }
+        process::exit(1);
+    }
+
+    if let Some(field) = &cli.count_by
+        && FIELDS
+            .iter()
+            .any(|(name, kind, _)| name == field && matches!(*kind, "array" | "object"))
+    {
+        if !cli.quiet {
+            report!("Error: --count-by needs a single-valued field, and '{field}' is a list");
+        }
+        process::exit(1);
+    }
+
+    if cli.append
+        && !cli.merge_csv
+        && let format = cli.format.resolve(cli.output.as_deref())
+        && matches!(
+            format,
+            OutputFormat::Json
+                | OutputFormat::Table
+                | OutputFormat::Md
+                | OutputFormat::Html
+                | OutputFormat::Parquet
+        )
+    {
+        if !cli.quiet {
+            // A second table would start with its own header mid-file
+            let suggestion = match format {
+                OutputFormat::Json => "; use --format ndjson",
+                OutputFormat::Table | OutputFormat::Md => "; use --format csv",
+                _ => "",
+            };
+            let name = format
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_string());
+            report!("Error: --append can't add to {name} output{suggestion}");
+        }
+        process::exit(1);
+    }
+
+    let parquet = !cli.merge_csv
+        && matches!(
+            cli.format.resolve(cli.output.as_deref()),
+            OutputFormat::Parquet
+        );
+    if parquet {
+        let problem = if cfg!(not(feature = "parquet")) {
+            Some("this build has no Parquet support (rebuild with --features parquet)")
+        } else if cli.output.is_none() {
+            Some("--format parquet needs --output FILE")
+        } else if cli.diff
+            || cli.unknown_only
+            || cli.tags_only
+            || cli.album_check
+            || cli.count_by.is_some()
+            || cli.duration_histogram
+            || cli.dump_lyrics
+            || cli.suggest_rename.is_some()
+            || cli.json_stream
+        {
+            Some("--format parquet only writes the full report")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            if !cli.quiet {
+                report!("Error: {problem}");
+            }
+            process::exit(1);
+        }
+    }
+
+    let tag_filter = match TagFilter::new(&cli) {
+        Ok(filter) => filter,
+        Err(e) => {
+            if !cli.quiet {
+                report!("Error: Invalid --tag-matches regex: {e}");
+            }
+            process::exit(1);
+        }
+    };
+
+    let exts = accepted_extensions(&cli);
+
+    if let Some(list) = &cli.follow_file_list {
+        let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed));
+        let mut out = open_stream_output(&cli);
+        if let Err(e) = follow_file_list(&mut out, list, &exts, &tag_filter, &cli) {
+            exit_write_error(e, &cli);
+        }
+        process::exit(if INTERRUPTED.load(Ordering::Relaxed) {
+            130
+        } else {
+            0
+        });
+    }
+
+    if cli.quiet_on_success {
+        *HELD.lock().unwrap_or_else(|e| e.into_inner()) = Some(Held::default());
+    }
+
+    // Inputs skipped with a warning, which fail the run under --strict
+    let mut warnings = 0usize;
+    let warn = |e| {
+        warnings += 1;
+        if !cli.quiet {
+            report!("Warning: {e}");
+        }
+    };
+    let files = if use_stdin {
+        collect_from_stdin(&exts, cli.walk(), warn)
+    } else {
+        collect_inputs(&exts, &cli, warn)
+    };
+
+    let files: Vec<Input> = match files {
+        Ok(files) => {
+            let files = files.into_iter().filter(|input| size_allowed(input, &cli));
+            match cli.sample {
+                Some(count) => {
+                    let seed = cli.seed.unwrap_or_else(|| {
+                        let seed = random_seed();
+                        if !cli.quiet {
+                            report!("Sampling with --seed {seed}");
+                        }
+                        seed
+                    });
+                    sample(files, count, &mut SplitMix64(seed))
+                }
+                None => files.take(cli.limit.unwrap_or(usize::MAX)).collect(),
+            }
+        }
+        Err(e) => {
+            release_held();
+            if !cli.quiet {
+                report!("Error: {e}");
+            }
+            process::exit(1);
+        }
+    };
+
+    if files.is_empty() {
+        release_held();
+        if !cli.quiet {
+            report!("Error: No audio files found");
+        }
+        process::exit(1);
+    }
+
+    if cli.diff && files.len() != 2 {
+        release_held();
+        if !cli.quiet {
+            report!(
+                "Error: --diff needs exactly two audio files, found {}",
+                files.len()
+            );
+        }
+        process::exit(1);
+    }
+
+    let mut stream = cli.json_stream.then(|| {
+        // Best effort: without a handler Ctrl-C still stops the run, just uncleanly
+        let _ = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed));
+        JsonArrayStream::new(open_stream_output(&cli), cli.jsonl_buffer)
+            .unwrap_or_else(|e| exit_write_error(e, &cli))
+    });
+    let mut results = Vec::new();
+    let mut result_inputs = Vec::new();
+    let mut error_count = if cli.strict { warnings } else { 0 };
+
+    let mut failures = Vec::new();
+
+    let on_error = cli.on_error();
+    let mut skipped_kinds: Vec<String> = Vec::new();
+    let mut skipped = 0usize;
+
+    if let Some(path) = &cli.cache {
+        load_cache(path, &cli);
+    }
+    let started = Instant::now();
+    let (mut processed, mut succeeded, mut bytes) = (0usize, 0usize, 0u64);
+    process_files(&files, &cli, |file, result| {
+        processed += 1;
+        if let Ok(info) = &result {
+            succeeded += 1;
+            bytes += info.file_size_bytes;
+        }
+
+        match result {
+            Ok(_) if cli.unknown_only => {}
+            Err(e) if cli.unknown_only => {
+                failures.push((input_path(file, &cli), e.to_string()));
+            }
+            Ok(info) if !tag_filter.keep(&info) || !codec_selected(&info, &cli.codec_only) => {}
+            Ok(mut info) => {
+                if let Some(dir) = &cli.extract_art {
+                    error_count += extract_art(dir, file, &info, &cli);
+                    // Only the details are reported; don't hold every image until the end
+                    info.art.iter_mut().for_each(|art| art.data = Vec::new());
+                }
+                match stream.as_mut() {
+                    Some(stream) => {
+                        if let Err(e) = stream.push(&record_json(&info, &cli)) {
+                            exit_write_error(e, &cli);
+                        }
+                    }
+                    None => {
+                        results.push(info);
+                        result_inputs.push(file);
+                    }
+                }
+            }
+            Err(e) => {
+                let mut note = String::new();
+                if on_error == OnError::SkipCodec
+                    && let Some(kind) = unsupported_kind(&e, file)
+                {
+                    if skipped_kinds.contains(&kind) {
+                        skipped += 1;
+                        return;
+                    }
+                    note = format!(" (skipping further {kind} files)");
+                    skipped_kinds.push(kind);
+                }
+
+                error_count += 1;
+                if !cli.quiet {
+                    report!("Error with {file}: {e}{note}");
+                }
+                if on_error == OnError::Abort {
+                    if let Some(stream) = stream.take() {
+                        let _ = stream.finish();
+                    }
+                    release_held();
+                    process::exit(1);
+                }
+            }
+        }
+    });
+    let elapsed = started.elapsed();
+    if let Some(path) = &cli.cache {
+        save_cache(path, &cli);
+    }
+
+    let groups = cli
+        .group_by
+        .as_deref()
+        .map(|field| group_results(&mut result_inputs, &mut results, field));
+
+    if let Some(expect) = &expect {
+        error_count += verify_results(&results, expect, cli.quiet);
+    } else if cli.unknown_only {
+        let mut out = open_output(&cli);
+        let format = cli.format.resolve(cli.output.as_deref());
+        if let Err(e) = output_failures(&mut out, &failures, &format).and_then(|_| out.flush())
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            exit_write_error(e, &cli);
+        }
+    } else if let Some(stream) = stream {
+        if let Err(e) = stream.finish() {
+            exit_write_error(e, &cli);
+        }
+    } else if let (Some(dir), Some(groups)) = (&cli.group_output, &groups) {
+        let format = if cli.merge_csv {
+            OutputFormat::Csv
+        } else {
+            cli.format.resolve(None)
+        };
+        error_count += write_group_reports(dir, &results, groups, &format, &cli);
+    } else if let Some(dir) = &cli.output_dir {
+        let format = if cli.merge_csv {
+            OutputFormat::Csv
+        } else {
+            cli.format.resolve(None)
+        };
+        error_count += write_reports(dir, &result_inputs, &results, &format, &cli);
+    } else if parquet && let Some(path) = &cli.output {
+        if let Err(e) = output_parquet(path, &results, &cli) {
+            exit_write_error(e, &cli);
+        }
+    } else if !results.is_empty() {
+        let mut out = open_output(&cli);
+
+        let format = if cli.merge_csv {
+            OutputFormat::Csv
+        } else {
+            cli.format.resolve(cli.output.as_deref())
+        };
+        let key_case = cli.key_case();
+        let written = if let Some(template) = &cli.suggest_rename {
+            suggest_renames(
+                &mut out,
+                &result_inputs,
+                &results,
+                template,
+                cli.rename,
+                cli.quiet,
+            )
+            .map(|problems| error_count += problems)
+        } else if cli.tags_only {
+            output_tags(&mut out, &results, &cli)
+        } else if cli.album_check {
+            output_album_check(&mut out, &album_checks(&result_inputs, &results), &format)
+        } else if let Some(field) = &cli.count_by {
+            output_counts(
+                &mut out,
+                &count_values(&results, field),
+                &format,
+                &cli.text_sep,
+            )
+        } else if cli.duration_histogram {
+            output_histogram(
+                &mut out,
+                &duration_bins(&results, cli.bin_size),
+                cli.bin_size,
+                &format,
+            )
+        } else if cli.dump_lyrics {
+            dump_lyrics(&mut out, &results, cli.quiet)
+        } else if cli.diff {
+            match &results[..] {
+                [a, b] => output_diff(&mut out, a, b, cli.diff_only, &format, key_case),
+                // One side failed under --keep-going and has already been reported
+                _ => Ok(()),
+            }
+        } else {
+            output_report(&mut out, &results, &format, &cli)
+        }
+        .and_then(|_| out.flush());
+
+        if let Err(e) = written
+            && e.kind() != io::ErrorKind::BrokenPipe
+        {
+            exit_write_error(e, &cli);
+        }
+    }
+
+    if cli.profile {
+        print_profile(processed, processed - succeeded, bytes, elapsed, cli.jobs());
+    }
+
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        release_held();
+        process::exit(130);
+    }
+
+    // Under --unknown-only, listing unreadable files is the run's whole output
+    if error_count > 0 || !failures.is_empty() {
+        release_held();
+    }
+
+    if error_count > 0 {
+        if !cli.quiet {
+            let skipped = if skipped > 0 {
+                format!(", {skipped} skipped")
+            } else {
+                String::new()
+            };
+            report!(
+                "Processed {} files, {error_count} error{}{skipped}",
+                files.len(),
+                if error_count == 1 { "" } else { "s" }
+            );
+        }
+        process::exit(1);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a tiny silent WAV analyzes to, with `tags` in place of its own.
+    fn info_with_tags(tags: &[(&str, &str)]) -> AudioInfo {
+        let samples = 800u32;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples * 2).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono, 8 kHz, 16 kB/s, 2-byte frames, 16 bits
+        for field in [1u16, 1] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(&8_000u32.to_le_bytes());
+        wav.extend_from_slice(&16_000u32.to_le_bytes());
+        for field in [2u16, 16] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples * 2).to_le_bytes());
+        wav.resize(wav.len() + samples as usize * 2, 0);
+
+        let file = tempfile::Builder::new().suffix(".wav").tempfile().unwrap();
+        fs::write(file.path(), wav).unwrap();
+        let mut info = audiometa::analyze(file.path()).unwrap();
+        info.tags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        info
+    }
+
+    fn cli(args: &[&str]) -> Cli {
+        Invocation::try_parse_from(["audiometa"].iter().chain(args))
+            .unwrap()
+            .cli
+    }
+
+    #[test]
+    fn tag_column_matches_snake_case_and_shows_key_case() {
+        let infos = [info_with_tags(&[("title", "Song"), ("artist", "Band")])];
+        let cli = cli(&["--tag", "title", "--key-case", "upper", "song.wav"]);
+
+        let columns = columns(&infos, &cli, false);
+        let (header, rows) = column_cells(&infos, &columns, cli.key_case());
+        let title = header
+            .iter()
+            .position(|name| name == "TITLE")
+            .expect("no TITLE column");
+        assert_eq!(rows[0][title], "Song");
+        assert!(
+            !header
+                .iter()
+                .any(|name| name == "title" || name == "ARTIST")
+        );
+    }
+
+    #[test]
+    fn text_output_shows_key_case() {
+        let infos = [info_with_tags(&[("title", "Song")])];
+        let cli = cli(&["--key-case", "upper", "song.wav"]);
+
+        let mut out = Vec::new();
+        output_text(&mut out, &infos, &cli).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.lines().any(|line| line == "TITLE: Song"), "{text}");
+    }
+
+    #[test]
+    fn csv_escape_quotes_line_breaks() {
+        // A sanitized tag value still holding a line break
+        assert_eq!(
+            csv_escape("\\u{1b}[31mred\\u{0}mid\r\nnext"),
+            "\"\\u{1b}[31mred\\u{0}mid\r\nnext\""
+        );
+        assert_eq!(csv_escape("say \"hi\", twice"), "\"say \"\"hi\"\", twice\"");
+        assert_eq!(csv_escape("plain"), "plain");
+    }
+
+    #[test]
+    fn default_extensions_include_matroska_audio() {
+        let cli = cli(&["song.mka"]);
+        for name in ["song.mka", "song.weba", "SONG.MKA"] {
+            assert!(
+                audiometa::is_audio_file(Path::new(name), &cli.ext),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn jobs_must_be_positive() {
+        assert!(Invocation::try_parse_from(["audiometa", "--jobs", "0", "song.wav"]).is_err());
+        assert_eq!(cli(&["--jobs", "1", "song.wav"]).jobs(), 1);
+    }
+
+    #[test]
+    fn art_data_uri_passes_on_only_known_media_types() {
+        let mut info = info_with_tags(&[]);
+        let picture = |media_type: &str| audiometa::Artwork {
+            kind: Some("front_cover".to_string()),
+            media_type: media_type.to_string(),
+            width: Some(1),
+            height: Some(1),
+            size_bytes: 3,
+            data: b"abc".to_vec(),
+        };
+
+        info.art = vec![picture("image/PNG")];
+        assert_eq!(
+            art_data_uri(&info, None, true).unwrap(),
+            "data:image/png;base64,YWJj"
+        );
+        info.art = vec![picture("image/jpeg\" onerror=\"alert(1)")];
+        assert_eq!(
+            art_data_uri(&info, None, true).unwrap(),
+            "data:application/octet-stream;base64,YWJj"
+        );
+    }
+
+    #[test]
+    fn html_escape_escapes_attribute_quotes() {
+        assert_eq!(html_escape("a\"b<c>&"), "a&quot;b&lt;c&gt;&amp;");
+    }
+
+    #[test]
+    fn preserve_tree_keeps_archived_reports_inside_the_output_dir() {
+        let mut archive =
+            tar::Builder::new(tempfile::Builder::new().suffix(".tar").tempfile().unwrap());
+        for name in ["/etc/abs.flac", "../../up.flac", "disc 1/ok.flac"] {
+            let mut header = tar::Header::new_gnu();
+            // Set directly, since the builder refuses such names
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(0);
+            header.set_cksum();
+            archive.append(&header, io::empty()).unwrap();
+        }
+        let file = archive.into_inner().unwrap();
+
+        let cli = cli(&["--output-dir", "out", "--preserve-tree", "x.tar"]);
+        let inputs = audiometa::archive_entries(file.path(), &["flac".to_string()]).unwrap();
+        let paths: Vec<_> = inputs
+            .iter()
+            .map(|input| report_path(Path::new("out"), input, &cli, "json"))
+            .collect();
+        let expected: Vec<PathBuf> = ["out/etc/abs.json", "out/up.json", "out/disc 1/ok.json"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(paths, expected);
+    }
+}